@@ -0,0 +1,26 @@
+#![no_main]
+
+// 运行: cargo fuzz run decode（在 crates/lambda-bytecode 目录下）
+// 任意输入都只能返回解码错误，不能 panic；能解码的指令重新编码后必须得到相同的字节
+use libfuzzer_sys::fuzz_target;
+use lambda_bytecode::bytecode::builder::BytecodeBuilder;
+use lambda_bytecode::bytecode::module::{FORMAT_VERSION, MIN_FORMAT_VERSION};
+use lambda_bytecode::bytecode::reader::BytecodeReader;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = BytecodeReader::new(data.to_vec()).read_module();
+    let _ = BytecodeReader::new(data.to_vec()).read_archive();
+    let _ = BytecodeReader::from_reader(data).read_module();
+    let _ = BytecodeReader::new(data.to_vec()).read_instructions();
+    for version in [MIN_FORMAT_VERSION, FORMAT_VERSION] {
+        let mut reader = BytecodeReader::with_version(data.to_vec(), version);
+        if let Ok(instruction) = reader.read_bytecode() {
+            let mut builder = BytecodeBuilder::with_version(version);
+            builder.write_bytecode(instruction.clone());
+            let decoded = BytecodeReader::with_version(builder.bytes.clone(), version).read_bytecode().unwrap();
+            let mut rewritten = BytecodeBuilder::with_version(version);
+            rewritten.write_bytecode(decoded);
+            assert_eq!(rewritten.bytes, builder.bytes, "{:?} does not round-trip", instruction);
+        }
+    }
+});
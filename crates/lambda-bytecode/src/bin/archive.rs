@@ -0,0 +1,54 @@
+use std::process::ExitCode;
+use lambda_bytecode::bytecode::archive::Archive;
+use lambda_bytecode::bytecode::builder::BytecodeBuilder;
+use lambda_bytecode::bytecode::module::Module;
+use lambda_bytecode::bytecode::reader::BytecodeReader;
+use lambda_bytecode::compiler::Compiler;
+use lambda_parser::parser::api::Parser;
+use lambda_parser::tokenizer::tokenizer::{SrcInfo, Tokenizer};
+
+// 用法: archive <output> [--entry <function>] <file>...
+// 把多个模块打包成一个归档，.ld 源文件会先编译，其余文件按二进制模块读取
+fn load(path: &str) -> Result<Module, String> {
+    if path.ends_with(".ld") {
+        let src = std::fs::read_to_string(path).map_err(|error| format!("Cannot read '{}': {}", path, error))?;
+        let src_info = SrcInfo { filename: path.to_string() };
+        let mut parser = Parser::new(Tokenizer::new(&src, src_info));
+        let program = parser.parse_program().map_err(|error| error.to_string())?;
+        return Compiler::compile_with_tokens(&program, path, &parser.token_buffer.tokens);
+    }
+    let bytes = std::fs::read(path).map_err(|error| format!("Cannot read '{}': {}", path, error))?;
+    BytecodeReader::new(bytes).read_module().map_err(|error| format!("{}: {}", path, error))
+}
+
+fn run(output: &str, arguments: &[String]) -> Result<(), String> {
+    let mut archive = Archive::new();
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        if argument == "--entry" {
+            let entry_point = arguments.next().ok_or("Expected a function name after '--entry'")?;
+            archive.manifest.entry_point = Some(entry_point.clone());
+            continue;
+        }
+        let name = std::path::Path::new(argument).file_name().and_then(|name| name.to_str()).unwrap_or(argument);
+        archive.add_module(name, load(argument)?);
+    }
+    let mut builder = BytecodeBuilder::new();
+    builder.write_archive(&archive).map_err(|error| error.to_string())?;
+    std::fs::write(output, builder.bytes).map_err(|error| format!("Cannot write '{}': {}", output, error))
+}
+
+fn main() -> ExitCode {
+    let arguments: Vec<String> = std::env::args().collect();
+    let [_, output, files @ ..] = arguments.as_slice() else {
+        eprintln!("Usage: archive <output> [--entry <function>] <file>...");
+        return ExitCode::FAILURE;
+    };
+    match run(output, files) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
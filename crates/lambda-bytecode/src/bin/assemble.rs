@@ -0,0 +1,28 @@
+use std::process::ExitCode;
+use lambda_bytecode::bytecode::assembler::assemble_module;
+use lambda_bytecode::bytecode::builder::BytecodeBuilder;
+
+// 用法: assemble <input> <output>
+// 把文本汇编（与 disassemble 的输出格式相同）写成二进制模块
+fn run(input: &str, output: &str) -> Result<(), String> {
+    let source = std::fs::read_to_string(input).map_err(|error| format!("Cannot read '{}': {}", input, error))?;
+    let module = assemble_module(&source)?;
+    let mut builder = BytecodeBuilder::new();
+    builder.write_module(&module).map_err(|error| error.to_string())?;
+    std::fs::write(output, builder.bytes).map_err(|error| format!("Cannot write '{}': {}", output, error))
+}
+
+fn main() -> ExitCode {
+    let arguments: Vec<String> = std::env::args().collect();
+    let [_, input, output] = arguments.as_slice() else {
+        eprintln!("Usage: assemble <input> <output>");
+        return ExitCode::FAILURE;
+    };
+    match run(input, output) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
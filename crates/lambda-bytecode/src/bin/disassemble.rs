@@ -0,0 +1,56 @@
+use std::fmt::Write;
+use std::process::ExitCode;
+use lambda_bytecode::bytecode::archive::ARCHIVE_MAGIC;
+use lambda_bytecode::bytecode::disassembler::{disassemble, disassemble_module};
+use lambda_bytecode::bytecode::module::{Module, MAGIC};
+use lambda_bytecode::bytecode::reader::BytecodeReader;
+use lambda_bytecode::compiler::Compiler;
+use lambda_parser::parser::api::Parser;
+use lambda_parser::tokenizer::tokenizer::{SrcInfo, Tokenizer};
+
+// 用法: disassemble <file>
+// .ld 源文件会先编译再反汇编；以魔数开头的文件按模块或归档读取；其余按裸函数体字节码处理
+fn run(path: &str) -> Result<String, String> {
+    if path.ends_with(".ld") {
+        let src = std::fs::read_to_string(path).map_err(|error| format!("Cannot read '{}': {}", path, error))?;
+        let src_info = SrcInfo { filename: path.to_string() };
+        let mut parser = Parser::new(Tokenizer::new(&src, src_info));
+        let program = parser.parse_program().map_err(|error| error.to_string())?;
+        return disassemble_module(&Compiler::compile_with_tokens(&program, path, &parser.token_buffer.tokens)?);
+    }
+    let bytes = std::fs::read(path).map_err(|error| format!("Cannot read '{}': {}", path, error))?;
+    if bytes.starts_with(&ARCHIVE_MAGIC) {
+        let archive = BytecodeReader::new(bytes).read_archive().map_err(|error| error.to_string())?;
+        let mut output = String::new();
+        if let Some(entry_point) = &archive.manifest.entry_point {
+            writeln!(output, "; entry point: {}", entry_point).unwrap();
+        }
+        for (name, module) in &archive.entries {
+            writeln!(output, "; module {}", name).unwrap();
+            output.push_str(&disassemble_module(module)?);
+        }
+        Ok(output)
+    } else if bytes.starts_with(&MAGIC) {
+        let module: Module = BytecodeReader::new(bytes).read_module().map_err(|error| error.to_string())?;
+        disassemble_module(&module)
+    } else {
+        disassemble(&bytes, None)
+    }
+}
+
+fn main() -> ExitCode {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("Usage: disassemble <file>");
+        return ExitCode::FAILURE;
+    };
+    match run(&path) {
+        Ok(listing) => {
+            print!("{}", listing);
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
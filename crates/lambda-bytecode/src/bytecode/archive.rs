@@ -0,0 +1,165 @@
+use crate::bytecode::builder::BytecodeBuilder;
+use crate::bytecode::error::{DecodeError, DecodeErrorKind, DecodeResult};
+use crate::bytecode::module::{Module, FORMAT_VERSION, MAGIC, MIN_FORMAT_VERSION};
+use crate::bytecode::reader::BytecodeReader;
+
+pub const ARCHIVE_MAGIC: [u8; 4] = *b"LMBA";
+pub const ARCHIVE_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub format_version: u16, // 归档中模块的格式版本
+    pub entry_point: Option<String>, // 入口函数的全限定名
+    pub packages: Vec<(String, Vec<usize>)>, // 包名 → 属于该包的模块下标，按包名排序
+}
+
+// 把多个编译后的模块打包成一个文件，格式为：
+// 魔数、归档版本、清单、条目表（每个条目是名称和一个完整的模块）
+#[derive(Debug, Clone, PartialEq)]
+pub struct Archive {
+    pub manifest: Manifest,
+    pub entries: Vec<(String, Module)>, // (条目名，通常是源文件名, 模块)
+}
+
+impl Manifest {
+    pub fn write(&self, builder: &mut BytecodeBuilder) {
+        builder.write_u16(self.format_version);
+        builder.write_bool(self.entry_point.is_some());
+        if let Some(entry_point) = &self.entry_point {
+            builder.write_string(entry_point);
+        }
+        builder.write_vec(&self.packages, |builder, (package, modules)| {
+            builder.write_string(package);
+            builder.write_vec(modules, |builder, index| builder.write_usize(*index));
+        });
+    }
+
+    pub fn read(reader: &mut BytecodeReader) -> DecodeResult<Self> {
+        let version_offset = reader.position;
+        let format_version = reader.read_u16()?;
+        if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&format_version) {
+            return Err(DecodeError::new(version_offset, "manifest", DecodeErrorKind::UnsupportedVersion(format_version)));
+        }
+        let entry_point = if reader.read_bool()? { Some(reader.read_string()?) } else { None };
+        let packages = reader.read_vec(|reader| {
+            let package = reader.read_string()?;
+            let modules = reader.read_vec(|reader| reader.read_usize())?;
+            Ok((package, modules))
+        })?;
+        Ok(Manifest { format_version, entry_point, packages })
+    }
+}
+
+impl Archive {
+    pub fn new() -> Self {
+        Archive {
+            manifest: Manifest { format_version: FORMAT_VERSION, entry_point: None, packages: Vec::new() },
+            entries: Vec::new(),
+        }
+    }
+
+    // 加入一个模块并更新包索引，返回模块下标
+    pub fn add_module(&mut self, name: &str, module: Module) -> usize {
+        let index = self.entries.len();
+        let packages = &mut self.manifest.packages;
+        match packages.binary_search_by(|(package, _)| package.as_str().cmp(module.package.as_str())) {
+            Ok(position) => packages[position].1.push(index),
+            Err(position) => packages.insert(position, (module.package.clone(), vec![index])),
+        }
+        self.entries.push((name.to_string(), module));
+        index
+    }
+
+    pub fn get_module(&self, name: &str) -> Option<&Module> {
+        self.entries.iter().find(|(entry, _)| entry == name).map(|(_, module)| module)
+    }
+
+    // 属于指定包的所有模块
+    pub fn get_package(&self, package: &str) -> Vec<&Module> {
+        match self.manifest.packages.iter().find(|(name, _)| name == package) {
+            Some((_, modules)) => modules.iter().filter_map(|index| self.entries.get(*index)).map(|(_, module)| module).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // 归档本身始终按当前版本编码，只有其中的模块按清单中的版本编码。
+    // 有模块无法按该版本输出时报错，此时不输出任何内容
+    pub fn write(&self, builder: &mut BytecodeBuilder) -> DecodeResult<()> {
+        let mut entries = Vec::new();
+        for (name, module) in &self.entries {
+            let mut payload = BytecodeBuilder::with_version(self.manifest.format_version);
+            payload.write_module(module)?;
+            entries.push((name, payload.bytes));
+        }
+        let version = std::mem::replace(&mut builder.version, FORMAT_VERSION);
+        builder.write_bytes(&ARCHIVE_MAGIC);
+        builder.write_u16(ARCHIVE_VERSION);
+        let mut manifest = BytecodeBuilder::new();
+        self.manifest.write(&mut manifest);
+        builder.write_usize(manifest.bytes.len());
+        builder.write_bytes(&manifest.bytes);
+        builder.write_vec(&entries, |builder, (name, payload)| {
+            builder.write_string(name);
+            builder.write_usize(payload.len());
+            builder.write_bytes(payload);
+        });
+        builder.version = version;
+        Ok(())
+    }
+
+    pub fn read(reader: &mut BytecodeReader) -> DecodeResult<Self> {
+        let version = std::mem::replace(&mut reader.version, FORMAT_VERSION);
+        let archive = Self::read_contents(reader);
+        reader.version = version;
+        archive
+    }
+
+    fn read_contents(reader: &mut BytecodeReader) -> DecodeResult<Self> {
+        let start = reader.position;
+        let magic = reader.read_bytes(ARCHIVE_MAGIC.len())?;
+        if magic != ARCHIVE_MAGIC {
+            return Err(DecodeError::new(start, "archive header", DecodeErrorKind::InvalidMagic));
+        }
+        let version_offset = reader.position;
+        let version = reader.read_u16()?;
+        if version != ARCHIVE_VERSION {
+            return Err(DecodeError::new(version_offset, "archive header", DecodeErrorKind::UnsupportedVersion(version)));
+        }
+        let length = reader.read_usize()?;
+        let base = reader.position;
+        let mut payload = BytecodeReader::new(reader.read_bytes(length)?);
+        let manifest = Manifest::read(&mut payload).map_err(|error| error.relocate(base))?;
+        if payload.has_next() {
+            return Err(DecodeError::new(base + payload.position, "manifest", DecodeErrorKind::SectionLengthMismatch));
+        }
+        let entries = reader.read_vec(|reader| {
+            let name = reader.read_string()?;
+            let length = reader.read_usize()?;
+            let base = reader.position;
+            let payload = reader.read_bytes(length)?;
+            // 模块头是魔数和版本，版本必须与清单一致
+            if let Some(version) = payload.get(MAGIC.len()..MAGIC.len() + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                && payload.starts_with(&MAGIC) && version != manifest.format_version {
+                return Err(DecodeError::new(base + MAGIC.len(), "module header", DecodeErrorKind::VersionMismatch(version)));
+            }
+            let module = BytecodeReader::new(payload).read_module().map_err(|error| error.relocate(base))?;
+            Ok((name, module))
+        })?;
+        // 每个模块恰好属于一个包，且与模块实际所属的包一致
+        let mut indexed = vec![false; entries.len()];
+        let consistent = manifest.packages.iter().all(|(package, modules)| {
+            modules.iter().all(|index| {
+                entries.get(*index).is_some_and(|(_, module)| module.package == *package)
+                    && !std::mem::replace(&mut indexed[*index], true)
+            })
+        }) && indexed.iter().all(|indexed| *indexed);
+        if !consistent {
+            return Err(DecodeError::new(reader.position, "manifest", DecodeErrorKind::InvalidManifest));
+        }
+        Ok(Archive { manifest, entries })
+    }
+}
+
+impl Default for Archive {
+    fn default() -> Self { Self::new() }
+}
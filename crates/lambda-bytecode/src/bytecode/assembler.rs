@@ -0,0 +1,579 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use bigdecimal::BigDecimal;
+use bigdecimal::num_bigint::BigInt;
+use crate::bytecode::builder::BytecodeBuilder;
+use crate::bytecode::bytecode::Bytecode;
+use crate::bytecode::constant::Constant;
+use crate::bytecode::module::{ClassDefinition, ExceptionHandler, FunctionDefinition, LineNumber, LocalVariable, Module};
+
+// 文本汇编格式与反汇编器的输出一致，另外支持：
+//   - 标签 `name:`，跳转指令可以写标签名代替字节偏移
+//   - 指令前的偏移可以省略，`;` 之后是注释；写成数字的跳转目标按行首的偏移解析
+//   - 常量操作数可以直接写字面量，例如 `LoadConst "hello"`、`Invoke fn "println" "(?)?"`、`NewObject class "Point"`
+//   - 函数体中的 `line <偏移或标签> <行>:<列>` 是行号表的一项
+//   - 函数体中的 `catch <开始> <结束> <处理代码> [类]` 是异常表的一项，省略类时捕获所有异常
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    String(String),
+    Char(char),
+    Punctuation(char),
+}
+
+fn unescape(chars: &mut std::iter::Peekable<std::str::Chars>, quote: char) -> Result<String, String> {
+    let mut value = String::new();
+    loop {
+        match chars.next().ok_or("Unterminated literal")? {
+            '\\' => match chars.next().ok_or("Unterminated literal")? {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                '0' => value.push('\0'),
+                'u' => {
+                    if chars.next() != Some('{') {
+                        return Err("Expected '{' in unicode escape".to_string());
+                    }
+                    let mut digits = String::new();
+                    for char in chars.by_ref() {
+                        if char == '}' {
+                            break;
+                        }
+                        digits.push(char);
+                    }
+                    let code = u32::from_str_radix(&digits, 16).map_err(|_| "Invalid unicode escape")?;
+                    value.push(char::from_u32(code).ok_or("Invalid unicode escape")?);
+                }
+                char => value.push(char),
+            },
+            char if char == quote => return Ok(value),
+            char => value.push(char),
+        }
+    }
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&char) = chars.peek() {
+        match char {
+            ';' => break, // 注释
+            _ if char.is_whitespace() => {
+                chars.next();
+            }
+            ':' | ',' | '(' | ')' | '=' => {
+                chars.next();
+                tokens.push(Token::Punctuation(char));
+            }
+            '"' => {
+                chars.next();
+                tokens.push(Token::String(unescape(&mut chars, '"')?));
+            }
+            '\'' => {
+                chars.next();
+                let value = unescape(&mut chars, '\'')?;
+                let mut value_chars = value.chars();
+                match (value_chars.next(), value_chars.next()) {
+                    (Some(char), None) => tokens.push(Token::Char(char)),
+                    _ => return Err(format!("Invalid character literal '{}'", value)),
+                }
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&char) = chars.peek() {
+                    if char.is_whitespace() || ":,()=\"';".contains(char) {
+                        break;
+                    }
+                    word.push(char);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Tokens {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Tokens {
+    fn new(tokens: Vec<Token>) -> Self { Tokens { tokens, position: 0 } }
+
+    fn peek(&self) -> Option<&Token> { self.tokens.get(self.position) }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn has_next(&self) -> bool { self.position < self.tokens.len() }
+
+    fn expect_word(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word),
+            token => Err(format!("Expected a name, but got {:?}", token)),
+        }
+    }
+
+    fn expect_string(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::String(value)) => Ok(value),
+            token => Err(format!("Expected a string, but got {:?}", token)),
+        }
+    }
+
+    fn expect_punctuation(&mut self, punctuation: char) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Punctuation(char)) if char == punctuation => Ok(()),
+            token => Err(format!("Expected '{}', but got {:?}", punctuation, token)),
+        }
+    }
+
+    fn expect_usize(&mut self) -> Result<usize, String> {
+        let word = self.expect_word()?;
+        usize::from_str(&word).map_err(|_| format!("Expected a number, but got '{}'", word))
+    }
+
+    fn expect_index(&mut self) -> Result<usize, String> {
+        let word = self.expect_word()?;
+        word.strip_prefix('#').and_then(|index| usize::from_str(index).ok())
+            .ok_or_else(|| format!("Expected a constant index, but got '{}'", word))
+    }
+
+    fn expect_end(&self) -> Result<(), String> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(format!("Unexpected {:?}", token)),
+        }
+    }
+}
+
+enum JumpTarget {
+    Offset(usize),
+    Label(String),
+}
+
+enum Block {
+    Header,
+    Constants,
+    Class(usize),
+    Function(usize),
+}
+
+// 函数体、行号表、异常表和局部变量表
+type AssembledCode = (Vec<u8>, Vec<LineNumber>, Vec<ExceptionHandler>, Vec<LocalVariable>);
+
+pub struct Assembler {
+    pub module: Module,
+    methods: Vec<(usize, usize, String)>, // (行号, 类下标, 方法名)
+    codes: Vec<Vec<(usize, Tokens)>>, // 每个函数的指令行
+}
+
+impl Assembler {
+    pub fn new(module: Module) -> Self {
+        Assembler { module, methods: Vec::new(), codes: Vec::new() }
+    }
+
+    fn parse_literal(&mut self, tokens: &mut Tokens) -> Result<Constant, String> {
+        match tokens.next().ok_or("Expected a constant")? {
+            Token::String(value) => Ok(Constant::String(value)),
+            Token::Char(value) => Ok(Constant::Char(value)),
+            Token::Word(word) if word == "class" => {
+                let name = self.parse_name(tokens)?;
+                Ok(Constant::Class(name))
+            }
+            Token::Word(word) if word == "fn" => {
+                let name = self.parse_name(tokens)?;
+                let signature = self.parse_name(tokens)?;
+                Ok(Constant::Function { name, signature })
+            }
+            Token::Word(word) => {
+                if let Some(value) = word.strip_suffix('n') && let Ok(value) = BigInt::from_str(value) {
+                    Ok(Constant::BigInt(value))
+                } else if let Some(value) = word.strip_suffix('d') && let Ok(value) = BigDecimal::from_str(value) {
+                    Ok(Constant::BigDecimal(value))
+                } else if let Ok(value) = i64::from_str(&word) {
+                    Ok(Constant::Int(value))
+                } else if let Ok(value) = f64::from_str(&word) {
+                    Ok(Constant::Float(value))
+                } else {
+                    Err(format!("Invalid constant '{}'", word))
+                }
+            }
+            token => Err(format!("Invalid constant {:?}", token)),
+        }
+    }
+
+    // `#index` 或者一个字符串字面量
+    fn parse_name(&mut self, tokens: &mut Tokens) -> Result<usize, String> {
+        match tokens.peek() {
+            Some(Token::String(_)) => {
+                let name = tokens.expect_string()?;
+                Ok(self.module.add_string(&name))
+            }
+            _ => tokens.expect_index(),
+        }
+    }
+
+    // `#index` 或者一个常量字面量
+    fn parse_operand(&mut self, tokens: &mut Tokens) -> Result<usize, String> {
+        match tokens.peek() {
+            Some(Token::Word(word)) if word.starts_with('#') => tokens.expect_index(),
+            _ => {
+                let constant = self.parse_literal(tokens)?;
+                Ok(self.module.add_constant(constant))
+            }
+        }
+    }
+
+    fn parse_jump_target(tokens: &mut Tokens) -> Result<JumpTarget, String> {
+        let word = tokens.expect_word()?;
+        Ok(match usize::from_str(&word) {
+            Ok(offset) => JumpTarget::Offset(offset),
+            Err(_) => JumpTarget::Label(word),
+        })
+    }
+
+    // 返回行首的偏移（如果有）、指令和跳转目标
+    fn parse_instruction(&mut self, tokens: &mut Tokens) -> Result<(Option<usize>, Bytecode, Option<JumpTarget>), String> {
+        let mut offset = None;
+        if let Some(Token::Word(word)) = tokens.peek() && word.starts_with(|char: char| char.is_ascii_digit()) {
+            offset = Some(tokens.expect_usize()?); // 反汇编输出中的偏移
+        }
+        let name = tokens.expect_word()?;
+        let mut target = None;
+        let bytecode = match name.as_str() {
+            "Metadata" => Bytecode::Metadata { source_file: tokens.expect_string()? },
+            "Nop" => Bytecode::Nop,
+            "Constant" => Bytecode::Constant(self.parse_literal(tokens)?),
+            "LoadConst" => Bytecode::LoadConst(self.parse_operand(tokens)?),
+            "GetObject" => Bytecode::GetObject(self.parse_operand(tokens)?),
+            "NewObject" => Bytecode::NewObject(self.parse_operand(tokens)?),
+            "Load" => Bytecode::Load,
+            "Store" => Bytecode::Store(tokens.expect_usize()?),
+            "LoadLocal" => Bytecode::LoadLocal(tokens.expect_usize()?),
+            "Pop" => Bytecode::Pop,
+            "Dup" => Bytecode::Dup,
+            "Swap" => Bytecode::Swap,
+            "Invoke" => Bytecode::Invoke(self.parse_operand(tokens)?),
+            "InvokeVirtual" => Bytecode::InvokeVirtual(self.parse_operand(tokens)?, tokens.expect_usize()?),
+            "InvokeSpecial" => Bytecode::InvokeSpecial(self.parse_operand(tokens)?, tokens.expect_usize()?),
+            "InvokeInterface" => Bytecode::InvokeInterface(self.parse_operand(tokens)?, tokens.expect_usize()?),
+            "Return" => Bytecode::Return,
+            "Jump" | "JumpIfTrue" | "JumpIfFalse" => {
+                target = Some(Self::parse_jump_target(tokens)?);
+                match name.as_str() {
+                    "Jump" => Bytecode::Jump(0),
+                    "JumpIfTrue" => Bytecode::JumpIfTrue(0),
+                    _ => Bytecode::JumpIfFalse(0),
+                }
+            }
+            "GetField" => Bytecode::GetField(self.parse_operand(tokens)?),
+            "SetField" => Bytecode::SetField(self.parse_operand(tokens)?),
+            "CheckCast" => Bytecode::CheckCast(self.parse_operand(tokens)?),
+            "InstanceOf" => Bytecode::InstanceOf(self.parse_operand(tokens)?),
+            "Throw" => Bytecode::Throw,
+            "Add" => Bytecode::Add,
+            "Sub" => Bytecode::Sub,
+            "Mul" => Bytecode::Mul,
+            "Div" => Bytecode::Div,
+            "Mod" => Bytecode::Mod,
+            "Pow" => Bytecode::Pow,
+            "Neg" => Bytecode::Neg,
+            "Pos" => Bytecode::Pos,
+            "Not" => Bytecode::Not,
+            "And" => Bytecode::And,
+            "Or" => Bytecode::Or,
+            "Equal" => Bytecode::Equal,
+            "NotEqual" => Bytecode::NotEqual,
+            "IdentityEqual" => Bytecode::IdentityEqual,
+            "IdentityNotEqual" => Bytecode::IdentityNotEqual,
+            "Less" => Bytecode::Less,
+            "LessEqual" => Bytecode::LessEqual,
+            "Greater" => Bytecode::Greater,
+            "GreaterEqual" => Bytecode::GreaterEqual,
+            _ => return Err(format!("Unknown instruction '{}'", name)),
+        };
+        tokens.expect_end()?;
+        Ok((offset, bytecode, target))
+    }
+
+    // `line <偏移或标签> <行>:<列>`
+    fn parse_line_number(tokens: &mut Tokens) -> Result<(JumpTarget, usize, usize), String> {
+        tokens.next();
+        let target = Self::parse_jump_target(tokens)?;
+        let line = tokens.expect_usize()?;
+        tokens.expect_punctuation(':')?;
+        let column = tokens.expect_usize()?;
+        tokens.expect_end()?;
+        Ok((target, line, column))
+    }
+
+    // `catch <开始> <结束> <处理代码> [类]`
+    fn parse_catch(&mut self, tokens: &mut Tokens) -> Result<([JumpTarget; 3], Option<usize>), String> {
+        tokens.next();
+        let targets = [Self::parse_jump_target(tokens)?, Self::parse_jump_target(tokens)?, Self::parse_jump_target(tokens)?];
+        let class = if tokens.has_next() { Some(self.parse_operand(tokens)?) } else { None };
+        tokens.expect_end()?;
+        Ok((targets, class))
+    }
+
+    // `local <开始> <结束> <下标> <名称> <类型>`
+    fn parse_local(&mut self, tokens: &mut Tokens) -> Result<([JumpTarget; 2], LocalVariable), String> {
+        tokens.next();
+        let targets = [Self::parse_jump_target(tokens)?, Self::parse_jump_target(tokens)?];
+        let slot = tokens.expect_usize()?;
+        let name = self.parse_operand(tokens)?;
+        let value_type = self.parse_operand(tokens)?;
+        tokens.expect_end()?;
+        Ok((targets, LocalVariable { name, value_type, slot, start: 0, end: 0 }))
+    }
+
+    fn assemble_lines(&mut self, lines: Vec<(usize, Tokens)>) -> Result<AssembledCode, String> {
+        let at = |line: usize| move |error: String| format!("line {}: {}", line, error);
+        let mut labels = HashMap::new();
+        let mut offsets = HashMap::new(); // 行首的偏移 → 指令下标
+        let mut instructions = Vec::new();
+        let mut line_numbers = Vec::new();
+        let mut catches = Vec::new();
+        let mut locals = Vec::new();
+        let mut end = None;
+        for (line, mut tokens) in lines {
+            if let [Token::Word(label), Token::Punctuation(':')] = tokens.tokens.as_slice() {
+                if labels.insert(label.clone(), instructions.len()).is_some() {
+                    return Err(at(line)(format!("Duplicate label '{}'", label)));
+                }
+                continue;
+            }
+            if tokens.peek() == Some(&Token::Word("line".to_string())) {
+                line_numbers.push((line, Self::parse_line_number(&mut tokens).map_err(at(line))?));
+                continue;
+            }
+            if tokens.peek() == Some(&Token::Word("catch".to_string())) {
+                catches.push((line, self.parse_catch(&mut tokens).map_err(at(line))?));
+                continue;
+            }
+            if tokens.peek() == Some(&Token::Word("local".to_string())) {
+                locals.push((line, self.parse_local(&mut tokens).map_err(at(line))?));
+                continue;
+            }
+            let (offset, bytecode, target) = self.parse_instruction(&mut tokens).map_err(at(line))?;
+            if let Some(offset) = offset {
+                offsets.insert(offset, instructions.len());
+                let mut builder = BytecodeBuilder::new();
+                bytecode.write(&mut builder);
+                end = Some(offset + builder.bytes.len()); // 跳转到函数末尾时没有对应的行
+            }
+            instructions.push((line, bytecode, target));
+        }
+        if let Some(end) = end {
+            offsets.entry(end).or_insert(instructions.len());
+        }
+        let resolve = |line: usize, target: JumpTarget| match target {
+            JumpTarget::Offset(offset) => offsets.get(&offset).copied()
+                .ok_or_else(|| at(line)(format!("Jump target {:04} is not an instruction offset", offset))),
+            JumpTarget::Label(label) => labels.get(&label).copied()
+                .ok_or_else(|| at(line)(format!("Undefined label '{}'", label))),
+        };
+        let mut resolved = Vec::with_capacity(instructions.len());
+        for (line, bytecode, target) in instructions {
+            resolved.push(match target {
+                Some(target) => bytecode.with_jump_target(resolve(line, target)?),
+                None => bytecode,
+            });
+        }
+        let mut builder = BytecodeBuilder::new();
+        let offsets = builder.write_instructions(&resolved);
+        let mut lines = Vec::with_capacity(line_numbers.len());
+        for (line, (target, source_line, column)) in line_numbers {
+            let offset = offsets[resolve(line, target)?];
+            lines.push(LineNumber { offset, line: source_line, column });
+        }
+        lines.sort_by_key(|line| line.offset);
+        let mut handlers = Vec::with_capacity(catches.len());
+        for (line, ([start, end, handler], class)) in catches {
+            handlers.push(ExceptionHandler {
+                start: offsets[resolve(line, start)?],
+                end: offsets[resolve(line, end)?],
+                handler: offsets[resolve(line, handler)?],
+                class,
+            });
+        }
+        let mut variables = Vec::with_capacity(locals.len());
+        for (line, ([start, end], variable)) in locals {
+            variables.push(LocalVariable {
+                start: offsets[resolve(line, start)?],
+                end: offsets[resolve(line, end)?],
+                ..variable
+            });
+        }
+        Ok((builder.bytes, lines, handlers, variables))
+    }
+
+    fn parse_class(&mut self, tokens: &mut Tokens) -> Result<ClassDefinition, String> {
+        let name = self.module.add_string(&tokens.expect_word()?);
+        let mut super_class = None;
+        let mut interfaces = Vec::new();
+        if tokens.peek() == Some(&Token::Punctuation(':')) {
+            tokens.next();
+            super_class = Some(self.module.add_string(&tokens.expect_word()?));
+        }
+        while tokens.has_next() {
+            tokens.expect_punctuation(',')?;
+            interfaces.push(self.module.add_string(&tokens.expect_word()?));
+        }
+        Ok(ClassDefinition { name, super_class, interfaces, fields: vec![], methods: vec![] })
+    }
+
+    fn parse_function(&mut self, tokens: &mut Tokens) -> Result<FunctionDefinition, String> {
+        let name = self.module.add_string(&tokens.expect_word()?);
+        tokens.expect_punctuation('(')?;
+        let mut parameters = 0;
+        let mut locals = 0;
+        while tokens.peek() != Some(&Token::Punctuation(')')) {
+            let key = tokens.expect_word()?;
+            tokens.expect_punctuation(':')?;
+            let value = tokens.expect_usize()?;
+            match key.as_str() {
+                "parameters" => parameters = value,
+                "locals" => locals = value,
+                _ => return Err(format!("Unknown function attribute '{}'", key)),
+            }
+            if tokens.peek() == Some(&Token::Punctuation(',')) {
+                tokens.next();
+            }
+        }
+        tokens.expect_punctuation(')')?;
+        tokens.expect_end()?;
+        Ok(FunctionDefinition { name, parameters, locals, ..FunctionDefinition::default() })
+    }
+
+    fn parse_line(&mut self, block: &mut Block, line: usize, mut tokens: Tokens) -> Result<(), String> {
+        let Some(Token::Word(keyword)) = tokens.peek().cloned() else {
+            return Err("Expected a declaration".to_string());
+        };
+        match keyword.as_str() {
+            "source" => {
+                tokens.next();
+                self.module.source_file = Some(tokens.expect_string()?);
+                tokens.expect_end()?;
+                *block = Block::Header;
+            }
+            "package" => {
+                tokens.next();
+                self.module.package = tokens.expect_word()?;
+                tokens.expect_end()?;
+                *block = Block::Header;
+            }
+            "constants" => {
+                tokens.next();
+                tokens.expect_punctuation(':')?;
+                tokens.expect_end()?;
+                *block = Block::Constants;
+            }
+            "class" => {
+                tokens.next();
+                let class = self.parse_class(&mut tokens)?;
+                self.module.classes.push(class);
+                *block = Block::Class(self.module.classes.len() - 1);
+            }
+            "native" => {
+                tokens.next();
+                let native = self.parse_operand(&mut tokens)?;
+                tokens.expect_end()?;
+                self.module.natives.push(native);
+                *block = Block::Header;
+            }
+            "fn" | "operator" => {
+                tokens.next();
+                if keyword == "operator" && tokens.expect_word()? != "fn" {
+                    return Err("Expected 'fn' after 'operator'".to_string());
+                }
+                let mut function = self.parse_function(&mut tokens)?;
+                function.operator = keyword == "operator";
+                self.module.functions.push(function);
+                self.codes.push(Vec::new());
+                *block = Block::Function(self.module.functions.len() - 1);
+            }
+            _ => match block {
+                Block::Constants => {
+                    // 常量按声明顺序放入常量池，保持反汇编输出中的索引
+                    let index = tokens.expect_index()?;
+                    if index != self.module.constants.len() {
+                        return Err(format!("Expected constant #{}, but got #{}", self.module.constants.len(), index));
+                    }
+                    tokens.expect_punctuation('=')?;
+                    let constant = self.parse_literal(&mut tokens)?;
+                    tokens.expect_end()?;
+                    self.module.constants.push(constant);
+                }
+                Block::Class(class) => {
+                    let class = *class;
+                    tokens.next();
+                    let name = tokens.expect_word()?;
+                    tokens.expect_end()?;
+                    match keyword.as_str() {
+                        "field" => {
+                            let name = self.module.add_string(&name);
+                            self.module.classes[class].fields.push(name);
+                        }
+                        "method" => self.methods.push((line, class, name)),
+                        _ => return Err(format!("Unknown class member '{}'", keyword)),
+                    }
+                }
+                Block::Function(function) => self.codes[*function].push((line, tokens)),
+                Block::Header => return Err(format!("Unexpected '{}'", keyword)),
+            },
+        }
+        Ok(())
+    }
+
+    fn tokenize_lines(source: &str) -> Result<Vec<(usize, Tokens)>, String> {
+        let mut lines = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let tokens = tokenize(line).map_err(|error| format!("line {}: {}", index + 1, error))?;
+            if !tokens.is_empty() {
+                lines.push((index + 1, Tokens::new(tokens)));
+            }
+        }
+        Ok(lines)
+    }
+
+    // 汇编一个完整的模块
+    pub fn assemble_module(mut self, source: &str) -> Result<Module, String> {
+        let mut block = Block::Header;
+        for (line, tokens) in Self::tokenize_lines(source)? {
+            self.parse_line(&mut block, line, tokens).map_err(|error| format!("line {}: {}", line, error))?;
+        }
+        for (index, lines) in std::mem::take(&mut self.codes).into_iter().enumerate() {
+            let (code, lines, handlers, variables) = self.assemble_lines(lines)?;
+            let function = &mut self.module.functions[index];
+            function.code = code;
+            function.lines = lines;
+            function.handlers = handlers;
+            function.variables = variables;
+        }
+        for (line, class, name) in std::mem::take(&mut self.methods) {
+            let method = self.module.functions.iter()
+                .position(|function| self.module.get_string(function.name) == Some(name.as_str()))
+                .ok_or_else(|| format!("line {}: Undefined method '{}'", line, name))?;
+            self.module.classes[class].methods.push(method);
+        }
+        Ok(self.module)
+    }
+
+    // 只汇编一段函数体，字面量操作数会加入当前模块的常量池
+    pub fn assemble_code(&mut self, source: &str) -> Result<Vec<u8>, String> {
+        let lines = Self::tokenize_lines(source)?;
+        Ok(self.assemble_lines(lines)?.0)
+    }
+}
+
+pub fn assemble_module(source: &str) -> Result<Module, String> {
+    Assembler::new(Module::new()).assemble_module(source)
+}
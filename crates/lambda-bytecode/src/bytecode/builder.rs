@@ -1,17 +1,77 @@
+use std::io::Write;
 use bigdecimal::BigDecimal;
 use bigdecimal::num_bigint::BigInt;
+use crate::bytecode::archive::Archive;
 use crate::bytecode::bytecode::Bytecode;
+use crate::bytecode::error::DecodeResult;
+use crate::bytecode::module::{Module, FORMAT_VERSION, VARINT_FORMAT_VERSION};
 
-pub struct BytecodeBuilder {
-    pub bytes: Vec<u8>,
+const BUFFER_SIZE: usize = 8192; // 输出到 io::Write 时缓冲的字节数
+
+// 跳转目标的标签，可以先跳转再绑定位置
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Label(usize);
+
+pub struct BytecodeBuilder<'a> {
+    pub bytes: Vec<u8>, // 输出到 io::Write 时只保存尚未写出的字节
+    pub version: u16, // 输出的模块格式版本，决定整数的编码方式
+    sink: Option<Box<dyn Write + 'a>>,
+    error: Option<std::io::Error>, // 写出失败后不再写入，由 flush 报告
+    instructions: Vec<Bytecode>, // 尚未输出的函数体，跳转目标是指令下标
+    labels: Vec<Option<usize>>, // 标签绑定的指令下标
+    fixups: Vec<(usize, Label)>, // 需要回填目标的跳转指令
 }
 
-impl BytecodeBuilder {
+impl<'a> BytecodeBuilder<'a> {
+
+    pub fn new() -> Self { Self::with_version(FORMAT_VERSION) }
+
+    pub fn with_version(version: u16) -> Self {
+        BytecodeBuilder {
+            bytes: Vec::new(),
+            version,
+            sink: None,
+            error: None,
+            instructions: Vec::new(),
+            labels: Vec::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    // 边写边输出到 writer，写完后需要调用 flush
+    pub fn from_writer(writer: impl Write + 'a) -> Self {
+        BytecodeBuilder { sink: Some(Box::new(writer)), ..Self::new() }
+    }
+
+    fn spill(&mut self) {
+        if self.bytes.len() < BUFFER_SIZE {
+            return;
+        }
+        if let Some(sink) = &mut self.sink {
+            if self.error.is_none() && let Err(error) = sink.write_all(&self.bytes) {
+                self.error = Some(error);
+            }
+            self.bytes.clear();
+        }
+    }
 
-    pub fn new() -> Self { BytecodeBuilder { bytes: Vec::new() } }
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        if let Some(sink) = &mut self.sink {
+            sink.write_all(&self.bytes)?;
+            self.bytes.clear();
+            sink.flush()?;
+        }
+        Ok(())
+    }
 
-    pub fn write_i8(&mut self, value: i8) { self.bytes.push(value as u8); }
-    pub fn write_u8(&mut self, value: u8) { self.bytes.push(value); }
+    pub fn write_i8(&mut self, value: i8) { self.write_u8(value as u8); }
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+        self.spill();
+    }
 
     pub fn write_i16(&mut self, value: i16) {
         self.write_i8((value >> 8) as i8);
@@ -69,7 +129,20 @@ impl BytecodeBuilder {
     }
 
     pub fn write_usize(&mut self, value: usize) {
-        self.write_u64(value as u64);
+        if self.version >= VARINT_FORMAT_VERSION {
+            self.write_varint(value as u64);
+        } else {
+            self.write_u64(value as u64);
+        }
+    }
+
+    // LEB128：每字节低 7 位存数据，最高位表示后面还有字节
+    pub fn write_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.write_u8((value as u8 & 0x7F) | 0x80);
+            value >>= 7;
+        }
+        self.write_u8(value as u8);
     }
 
     pub fn write_char(&mut self, value: char) {
@@ -91,20 +164,131 @@ impl BytecodeBuilder {
     }
 
     pub fn write_big_decimal(&mut self, value: BigDecimal) {
-        self.write_string(value.to_string().as_str())
+        let (digits, scale) = value.into_bigint_and_exponent();
+        self.write_big_int(digits); // 有效数字
+        self.write_i64(scale); // 小数位数
     }
 
     pub fn write_big_int(&mut self, value: BigInt) {
-        self.write_string(value.to_string().as_str())
+        let bytes = value.to_signed_bytes_be();
+        self.write_usize(bytes.len()); // 字节长度
+        self.write_bytes(&bytes); // 大端补码
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+        self.spill();
     }
 
     pub fn write_bytecode(&mut self, bytecode: Bytecode) {
         bytecode.write(self);
     }
 
+    // 输出一个函数体，跳转指令的目标是指令下标，这里换算为字节偏移。
+    // 变长编码下偏移的长度取决于偏移本身，所以从最短的编码开始反复布局直到不再变化。
+    // 返回每条指令的偏移，最后一项是函数体的长度
+    pub fn write_instructions(&mut self, instructions: &[Bytecode]) -> Vec<usize> {
+        let get_size = |instruction: &Bytecode| {
+            let mut builder = BytecodeBuilder::with_version(self.version);
+            instruction.write(&mut builder);
+            builder.bytes.len()
+        };
+        let mut sizes: Vec<usize> = instructions.iter().map(|instruction| get_size(&instruction.with_jump_target(0))).collect();
+        let offsets = loop {
+            let mut offsets = Vec::with_capacity(instructions.len() + 1);
+            let mut offset = 0;
+            for size in &sizes {
+                offsets.push(offset);
+                offset += size;
+            }
+            offsets.push(offset);
+            let relocated: Vec<usize> = instructions.iter().map(|instruction| match instruction.get_jump_target() {
+                Some(target) => get_size(&instruction.with_jump_target(offsets[target])),
+                None => get_size(instruction),
+            }).collect();
+            if relocated == sizes {
+                break offsets;
+            }
+            sizes = relocated;
+        };
+        for instruction in instructions {
+            match instruction.get_jump_target() {
+                Some(target) => self.write_bytecode(instruction.with_jump_target(offsets[target])),
+                None => self.write_bytecode(instruction.clone()),
+            }
+        }
+        offsets
+    }
+
+    // 以下方法逐条记录函数体，跳转目标可以是之后才绑定的标签，由 finish_instructions 统一回填并输出
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    // 把标签绑定到下一条记录的指令
+    pub fn bind_label(&mut self, label: Label) -> Result<(), String> {
+        let target = self.labels.get_mut(label.0).ok_or_else(|| format!("Label #{} is unknown", label.0))?;
+        if target.is_some() {
+            return Err(format!("Label #{} is already bound", label.0));
+        }
+        *target = Some(self.instructions.len());
+        Ok(())
+    }
+
+    // 记录一条指令，返回其下标。跳转指令的目标是指令下标
+    pub fn emit(&mut self, instruction: Bytecode) -> usize {
+        self.instructions.push(instruction);
+        self.instructions.len() - 1
+    }
+
+    // 记录一条跳转到标签的指令，目标先置为 0
+    pub fn emit_jump(&mut self, jump: fn(usize) -> Bytecode, label: Label) -> usize {
+        let index = self.emit(jump(0));
+        self.fixups.push((index, label));
+        index
+    }
+
+    // 已记录的指令个数，即下一条指令的下标
+    pub fn get_instruction_count(&self) -> usize { self.instructions.len() }
+
+    // 回填所有跳转到标签的指令，取出记录的函数体，跳转目标为指令下标。
+    // 标签在取出后失效，之前创建的标签不能用于下一个函数体
+    pub fn resolve_labels(&mut self) -> Result<Vec<Bytecode>, String> {
+        let mut targets = Vec::with_capacity(self.fixups.len());
+        for (_, label) in &self.fixups {
+            match self.labels.get(label.0) {
+                Some(Some(target)) => targets.push(*target),
+                Some(None) => return Err(format!("Label #{} is not bound", label.0)),
+                None => return Err(format!("Label #{} is unknown", label.0)),
+            }
+        }
+        let mut instructions = std::mem::take(&mut self.instructions);
+        for ((index, _), target) in self.fixups.drain(..).zip(targets) {
+            instructions[index] = instructions[index].with_jump_target(target);
+        }
+        self.labels.clear();
+        Ok(instructions)
+    }
+
+    // 回填标签后输出记录的函数体，返回值与 write_instructions 相同
+    pub fn finish_instructions(&mut self) -> Result<Vec<usize>, String> {
+        let instructions = self.resolve_labels()?;
+        Ok(self.write_instructions(&instructions))
+    }
+
+    pub fn write_module(&mut self, module: &Module) -> DecodeResult<()> {
+        module.write(self)
+    }
+
+    pub fn write_archive(&mut self, archive: &Archive) -> DecodeResult<()> {
+        archive.write(self)
+    }
+
     pub fn write_vec<T, F>(&mut self, vec: &Vec<T>, write_element: F)
     where
-        F: Fn(&mut BytecodeBuilder, &T),
+        F: Fn(&mut BytecodeBuilder<'a>, &T),
     {
         self.write_usize(vec.len());
         for element in vec {
@@ -1,15 +1,40 @@
 use crate::bytecode::builder::BytecodeBuilder;
+use crate::bytecode::constant::Constant;
+use crate::bytecode::error::{DecodeError, DecodeErrorKind, DecodeResult};
 use crate::bytecode::reader::BytecodeReader;
 
 
-#[derive(Debug, Clone)]
+// 运算指令和运算符函数名一一对应，两个方向的查找都由这张表得出
+pub const OPERATOR_FUNCTIONS: &[(Bytecode, &str)] = &[
+    (Bytecode::Add, "plus"),
+    (Bytecode::Sub, "minus"),
+    (Bytecode::Mul, "times"),
+    (Bytecode::Div, "div"),
+    (Bytecode::Mod, "rem"),
+    (Bytecode::Pow, "pow"),
+    (Bytecode::Neg, "unaryMinus"),
+    (Bytecode::Pos, "unaryPlus"),
+    (Bytecode::Not, "not"),
+    (Bytecode::And, "and"),
+    (Bytecode::Or, "or"),
+    (Bytecode::Equal, "equals"),
+    (Bytecode::NotEqual, "notEquals"),
+    (Bytecode::IdentityEqual, "identityEquals"),
+    (Bytecode::IdentityNotEqual, "identityNotEquals"),
+    (Bytecode::Less, "less"),
+    (Bytecode::LessEqual, "lessOrEquals"),
+    (Bytecode::Greater, "greater"),
+    (Bytecode::GreaterEqual, "greaterOrEquals"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Bytecode {
     // 元信息和基本操作
     Metadata { source_file: String }, // 文件的元信息
     Nop, // 无操作
 
     // 常量池操作
-    Constant(String), // 常量池的内容
+    Constant(Constant), // 常量池的内容
     LoadConst(usize), // 从常量池加载常量 #index: 常量值在常量池中的索引
 
     // 对象操作
@@ -25,7 +50,11 @@ pub enum Bytecode {
     Swap, // 交换栈顶两个元素
 
     // 函数调用
-    Invoke(usize), // 调用函数 #index: 函数名在常量池中的索引
+    Invoke(usize), // 调用顶层函数 #index: 函数在常量池中的索引，参数个数由签名决定
+    // 以下调用的接收者在参数之下，参数个数不包含接收者，函数名为声明该方法的类名加方法名
+    InvokeVirtual(usize, usize), // 按接收者的实际类型查找方法 #index, 参数个数
+    InvokeSpecial(usize, usize), // 不经过动态分派，从指定的类开始查找方法：super 调用和初始化函数 #index, 参数个数
+    InvokeInterface(usize, usize), // 接收者必须实现该接口，按实际类型查找方法 #index, 参数个数
     Return, // 返回栈顶的对象
 
     // 控制流
@@ -43,6 +72,27 @@ pub enum Bytecode {
 
     // 异常处理
     Throw, // 抛出异常
+
+    // 运算：操作数为内置类型时直接计算，否则调用左操作数所属类中对应的运算符函数
+    Add, // 加法
+    Sub, // 减法
+    Mul, // 乘法
+    Div, // 除法
+    Mod, // 取余
+    Pow, // 乘方
+    Neg, // 取负
+    Pos, // 一元加
+    Not, // 逻辑非
+    And, // 按位与，布尔值为不短路的与
+    Or, // 按位或，布尔值为不短路的或
+    Equal, // 相等 ==
+    NotEqual, // 不相等 !=
+    IdentityEqual, // 同一对象 ===
+    IdentityNotEqual, // 不是同一对象 !==
+    Less, // 小于
+    LessEqual, // 小于等于
+    Greater, // 大于
+    GreaterEqual, // 大于等于
 }
 
 type Code = u8;
@@ -72,88 +122,221 @@ impl Bytecode {
             Bytecode::CheckCast(_) => 0x13,
             Bytecode::InstanceOf(_) => 0x14,
             Bytecode::Throw => 0x15,
+            Bytecode::Add => 0x16,
+            Bytecode::Sub => 0x17,
+            Bytecode::Mul => 0x18,
+            Bytecode::Div => 0x19,
+            Bytecode::Mod => 0x1A,
+            Bytecode::Pow => 0x1B,
+            Bytecode::Neg => 0x1C,
+            Bytecode::Pos => 0x1D,
+            Bytecode::Not => 0x1E,
+            Bytecode::And => 0x1F,
+            Bytecode::Or => 0x20,
+            Bytecode::Equal => 0x21,
+            Bytecode::NotEqual => 0x22,
+            Bytecode::IdentityEqual => 0x23,
+            Bytecode::IdentityNotEqual => 0x24,
+            Bytecode::Less => 0x25,
+            Bytecode::LessEqual => 0x26,
+            Bytecode::Greater => 0x27,
+            Bytecode::GreaterEqual => 0x28,
+            Bytecode::InvokeVirtual(..) => 0x29,
+            Bytecode::InvokeSpecial(..) => 0x2A,
+            Bytecode::InvokeInterface(..) => 0x2B,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Bytecode::Metadata { .. } => "Metadata",
+            Bytecode::Nop => "Nop",
+            Bytecode::Constant(_) => "Constant",
+            Bytecode::LoadConst(_) => "LoadConst",
+            Bytecode::GetObject(_) => "GetObject",
+            Bytecode::NewObject(_) => "NewObject",
+            Bytecode::Load => "Load",
+            Bytecode::Store(_) => "Store",
+            Bytecode::LoadLocal(_) => "LoadLocal",
+            Bytecode::Pop => "Pop",
+            Bytecode::Dup => "Dup",
+            Bytecode::Swap => "Swap",
+            Bytecode::Invoke(_) => "Invoke",
+            Bytecode::Return => "Return",
+            Bytecode::Jump(_) => "Jump",
+            Bytecode::JumpIfTrue(_) => "JumpIfTrue",
+            Bytecode::JumpIfFalse(_) => "JumpIfFalse",
+            Bytecode::GetField(_) => "GetField",
+            Bytecode::SetField(_) => "SetField",
+            Bytecode::CheckCast(_) => "CheckCast",
+            Bytecode::InstanceOf(_) => "InstanceOf",
+            Bytecode::Throw => "Throw",
+            Bytecode::Add => "Add",
+            Bytecode::Sub => "Sub",
+            Bytecode::Mul => "Mul",
+            Bytecode::Div => "Div",
+            Bytecode::Mod => "Mod",
+            Bytecode::Pow => "Pow",
+            Bytecode::Neg => "Neg",
+            Bytecode::Pos => "Pos",
+            Bytecode::Not => "Not",
+            Bytecode::And => "And",
+            Bytecode::Or => "Or",
+            Bytecode::Equal => "Equal",
+            Bytecode::NotEqual => "NotEqual",
+            Bytecode::IdentityEqual => "IdentityEqual",
+            Bytecode::IdentityNotEqual => "IdentityNotEqual",
+            Bytecode::Less => "Less",
+            Bytecode::LessEqual => "LessEqual",
+            Bytecode::Greater => "Greater",
+            Bytecode::GreaterEqual => "GreaterEqual",
+            Bytecode::InvokeVirtual(..) => "InvokeVirtual",
+            Bytecode::InvokeSpecial(..) => "InvokeSpecial",
+            Bytecode::InvokeInterface(..) => "InvokeInterface",
+        }
+    }
+
+    pub fn get_jump_target(&self) -> Option<usize> {
+        match self {
+            Bytecode::Jump(target) | Bytecode::JumpIfTrue(target) | Bytecode::JumpIfFalse(target) => Some(*target),
+            _ => None,
+        }
+    }
+
+    // 替换跳转指令的目标，其他指令原样返回
+    pub fn with_jump_target(&self, target: usize) -> Self {
+        match self {
+            Bytecode::Jump(_) => Bytecode::Jump(target),
+            Bytecode::JumpIfTrue(_) => Bytecode::JumpIfTrue(target),
+            Bytecode::JumpIfFalse(_) => Bytecode::JumpIfFalse(target),
+            _ => self.clone(),
         }
     }
 
+    // 运算指令对应的运算符函数名，回退到用户定义的 `operator fn` 时使用
+    pub fn get_operator_function_name(&self) -> Option<&'static str> {
+        OPERATOR_FUNCTIONS.iter().find(|(bytecode, _)| bytecode == self).map(|(_, name)| *name)
+    }
+
+    // 运算符函数名对应的指令，没有用户定义的 operator fn 时直接生成该指令
+    pub fn from_operator_function_name(function_name: &str) -> Option<Self> {
+        OPERATOR_FUNCTIONS.iter().find(|(_, name)| *name == function_name).map(|(bytecode, _)| bytecode.clone())
+    }
+
     pub fn write_code(&self, builder: &mut BytecodeBuilder) {
         builder.write_u8(self.get_code());
     }
 
-    pub fn read_code(reader: &mut BytecodeReader) -> Option<Code> {
+    pub fn read_code(reader: &mut BytecodeReader) -> DecodeResult<Code> {
         reader.read_u8()
     }
 
-    pub fn read(reader: &mut BytecodeReader) -> Option<Self> {
+    pub fn read(reader: &mut BytecodeReader) -> DecodeResult<Self> {
+        let start = reader.position;
         let code = Self::read_code(reader)?;
         match code {
             0x00 => {
                 let source_file = reader.read_string()?;
-                Some(Bytecode::Metadata { source_file })
+                Ok(Bytecode::Metadata { source_file })
             },
-            0x01 => Some(Bytecode::Nop),
+            0x01 => Ok(Bytecode::Nop),
             0x02 => {
-                let value = reader.read_string()?;
-                Some(Bytecode::Constant(value))
+                let value = Constant::read(reader)?;
+                Ok(Bytecode::Constant(value))
             },
             0x03 => {
                 let index = reader.read_usize()?;
-                Some(Bytecode::LoadConst(index))
+                Ok(Bytecode::LoadConst(index))
             },
             0x04 => {
                 let index = reader.read_usize()?;
-                Some(Bytecode::GetObject(index))
+                Ok(Bytecode::GetObject(index))
             },
             0x05 => {
                 let index = reader.read_usize()?;
-                Some(Bytecode::NewObject(index))
+                Ok(Bytecode::NewObject(index))
             },
-            0x06 => Some(Bytecode::Load),
+            0x06 => Ok(Bytecode::Load),
             0x07 => {
                 let index = reader.read_usize()?;
-                Some(Bytecode::Store(index))
+                Ok(Bytecode::Store(index))
             },
             0x08 => {
                 let index = reader.read_usize()?;
-                Some(Bytecode::LoadLocal(index))
+                Ok(Bytecode::LoadLocal(index))
             },
-            0x09 => Some(Bytecode::Pop),
-            0x0A => Some(Bytecode::Dup),
-            0x0B => Some(Bytecode::Swap),
+            0x09 => Ok(Bytecode::Pop),
+            0x0A => Ok(Bytecode::Dup),
+            0x0B => Ok(Bytecode::Swap),
             0x0C => {
                 let index = reader.read_usize()?;
-                Some(Bytecode::Invoke(index))
+                Ok(Bytecode::Invoke(index))
             },
-            0x0D => Some(Bytecode::Return),
+            0x0D => Ok(Bytecode::Return),
             0x0E => {
                 let offset = reader.read_usize()?;
-                Some(Bytecode::Jump(offset))
+                Ok(Bytecode::Jump(offset))
             },
             0x0F => {
                 let offset = reader.read_usize()?;
-                Some(Bytecode::JumpIfTrue(offset))
+                Ok(Bytecode::JumpIfTrue(offset))
             },
             0x10 => {
                 let offset = reader.read_usize()?;
-                Some(Bytecode::JumpIfFalse(offset))
+                Ok(Bytecode::JumpIfFalse(offset))
             },
             0x11 => {
                 let index = reader.read_usize()?;
-                Some(Bytecode::GetField(index))
+                Ok(Bytecode::GetField(index))
             },
             0x12 => {
                 let index = reader.read_usize()?;
-                Some(Bytecode::SetField(index))
+                Ok(Bytecode::SetField(index))
             },
             0x13 => {
                 let index = reader.read_usize()?;
-                Some(Bytecode::CheckCast(index))
+                Ok(Bytecode::CheckCast(index))
             },
             0x14 => {
                 let index = reader.read_usize()?;
-                Some(Bytecode::InstanceOf(index))
+                Ok(Bytecode::InstanceOf(index))
             },
-            0x15 => Some(Bytecode::Throw),
-            _ => None,
+            0x15 => Ok(Bytecode::Throw),
+            0x16 => Ok(Bytecode::Add),
+            0x17 => Ok(Bytecode::Sub),
+            0x18 => Ok(Bytecode::Mul),
+            0x19 => Ok(Bytecode::Div),
+            0x1A => Ok(Bytecode::Mod),
+            0x1B => Ok(Bytecode::Pow),
+            0x1C => Ok(Bytecode::Neg),
+            0x1D => Ok(Bytecode::Pos),
+            0x1E => Ok(Bytecode::Not),
+            0x1F => Ok(Bytecode::And),
+            0x20 => Ok(Bytecode::Or),
+            0x21 => Ok(Bytecode::Equal),
+            0x22 => Ok(Bytecode::NotEqual),
+            0x23 => Ok(Bytecode::IdentityEqual),
+            0x24 => Ok(Bytecode::IdentityNotEqual),
+            0x25 => Ok(Bytecode::Less),
+            0x26 => Ok(Bytecode::LessEqual),
+            0x27 => Ok(Bytecode::Greater),
+            0x28 => Ok(Bytecode::GreaterEqual),
+            0x29 => {
+                let index = reader.read_usize()?;
+                let arguments = reader.read_usize()?;
+                Ok(Bytecode::InvokeVirtual(index, arguments))
+            },
+            0x2A => {
+                let index = reader.read_usize()?;
+                let arguments = reader.read_usize()?;
+                Ok(Bytecode::InvokeSpecial(index, arguments))
+            },
+            0x2B => {
+                let index = reader.read_usize()?;
+                let arguments = reader.read_usize()?;
+                Ok(Bytecode::InvokeInterface(index, arguments))
+            },
+            _ => Err(DecodeError::new(start, "instruction", DecodeErrorKind::UnknownOpcode(code))),
         }
     }
 
@@ -165,7 +348,7 @@ impl Bytecode {
             },
             Bytecode::Nop => {},
             Bytecode::Constant(value) => {
-                builder.write_string(value);
+                value.write(builder);
             },
             Bytecode::LoadConst(index) => {
                 builder.write_usize(*index);
@@ -212,6 +395,31 @@ impl Bytecode {
                 builder.write_usize(*index);
             },
             Bytecode::Throw => {},
+            Bytecode::Add => {},
+            Bytecode::Sub => {},
+            Bytecode::Mul => {},
+            Bytecode::Div => {},
+            Bytecode::Mod => {},
+            Bytecode::Pow => {},
+            Bytecode::Neg => {},
+            Bytecode::Pos => {},
+            Bytecode::Not => {},
+            Bytecode::And => {},
+            Bytecode::Or => {},
+            Bytecode::Equal => {},
+            Bytecode::NotEqual => {},
+            Bytecode::IdentityEqual => {},
+            Bytecode::IdentityNotEqual => {},
+            Bytecode::Less => {},
+            Bytecode::LessEqual => {},
+            Bytecode::Greater => {},
+            Bytecode::GreaterEqual => {},
+            Bytecode::InvokeVirtual(index, arguments)
+            | Bytecode::InvokeSpecial(index, arguments)
+            | Bytecode::InvokeInterface(index, arguments) => {
+                builder.write_usize(*index);
+                builder.write_usize(*arguments);
+            },
         }
     }
 }
@@ -0,0 +1,94 @@
+use std::fmt::{Display, Formatter};
+use bigdecimal::BigDecimal;
+use bigdecimal::num_bigint::BigInt;
+use crate::bytecode::builder::BytecodeBuilder;
+use crate::bytecode::error::{DecodeError, DecodeErrorKind, DecodeResult};
+use crate::bytecode::reader::BytecodeReader;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    BigInt(BigInt),
+    Float(f64),
+    BigDecimal(BigDecimal),
+    Char(char),
+    String(String),
+    Class(usize), // #index: 类名在常量池中的索引
+    Function { name: usize, signature: usize }, // #index: 函数名和签名在常量池中的索引
+}
+
+type Tag = u8;
+
+impl Constant {
+    pub fn get_tag(&self) -> Tag {
+        match self {
+            Constant::Int(_) => 0x01,
+            Constant::BigInt(_) => 0x02,
+            Constant::Float(_) => 0x03,
+            Constant::BigDecimal(_) => 0x04,
+            Constant::Char(_) => 0x05,
+            Constant::String(_) => 0x06,
+            Constant::Class(_) => 0x07,
+            Constant::Function { .. } => 0x08,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Constant::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn read(reader: &mut BytecodeReader) -> DecodeResult<Self> {
+        let start = reader.position;
+        let tag = reader.read_u8()?;
+        match tag {
+            0x01 => Ok(Constant::Int(reader.read_i64()?)),
+            0x02 => Ok(Constant::BigInt(reader.read_big_int()?)),
+            0x03 => Ok(Constant::Float(reader.read_f64()?)),
+            0x04 => Ok(Constant::BigDecimal(reader.read_big_decimal()?)),
+            0x05 => Ok(Constant::Char(reader.read_char()?)),
+            0x06 => Ok(Constant::String(reader.read_string()?)),
+            0x07 => Ok(Constant::Class(reader.read_usize()?)),
+            0x08 => {
+                let name = reader.read_usize()?;
+                let signature = reader.read_usize()?;
+                Ok(Constant::Function { name, signature })
+            },
+            _ => Err(DecodeError::new(start, "constant", DecodeErrorKind::UnknownConstantTag(tag))),
+        }
+    }
+
+    pub fn write(&self, builder: &mut BytecodeBuilder) {
+        builder.write_u8(self.get_tag());
+        match self {
+            Constant::Int(value) => builder.write_i64(*value),
+            Constant::BigInt(value) => builder.write_big_int(value.clone()),
+            Constant::Float(value) => builder.write_f64(*value),
+            Constant::BigDecimal(value) => builder.write_big_decimal(value.clone()),
+            Constant::Char(value) => builder.write_char(*value),
+            Constant::String(value) => builder.write_string(value),
+            Constant::Class(name) => builder.write_usize(*name),
+            Constant::Function { name, signature } => {
+                builder.write_usize(*name);
+                builder.write_usize(*signature);
+            },
+        }
+    }
+}
+
+impl Display for Constant {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constant::Int(value) => write!(f, "{}", value),
+            Constant::BigInt(value) => write!(f, "{}n", value),
+            Constant::Float(value) => write!(f, "{:?}", value),
+            Constant::BigDecimal(value) => write!(f, "{}d", value),
+            Constant::Char(value) => write!(f, "{:?}", value),
+            Constant::String(value) => write!(f, "{:?}", value),
+            Constant::Class(name) => write!(f, "class #{}", name),
+            Constant::Function { name, signature } => write!(f, "fn #{} #{}", name, signature),
+        }
+    }
+}
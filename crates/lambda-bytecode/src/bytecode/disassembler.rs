@@ -0,0 +1,143 @@
+use std::fmt::Write;
+use crate::bytecode::bytecode::Bytecode;
+use crate::bytecode::constant::Constant;
+use crate::bytecode::module::Module;
+use crate::bytecode::reader::BytecodeReader;
+
+// 把常量池索引解析成便于阅读的值，类和函数显示为它们的名字
+pub fn describe_constant(module: &Module, index: usize) -> Option<String> {
+    match module.get_constant(index)? {
+        Constant::Class(name) => Some(format!("{:?}", module.get_string(*name)?)),
+        Constant::Function { name, .. } => Some(format!("{:?}", module.get_string(*name)?)),
+        constant => Some(constant.to_string()),
+    }
+}
+
+pub fn describe_bytecode(bytecode: &Bytecode, module: Option<&Module>) -> String {
+    let name = bytecode.get_name();
+    match bytecode {
+        Bytecode::Metadata { source_file } => format!("{} {:?}", name, source_file),
+        Bytecode::Constant(value) => format!("{} {}", name, value),
+        Bytecode::LoadConst(index)
+        | Bytecode::GetObject(index)
+        | Bytecode::NewObject(index)
+        | Bytecode::Invoke(index)
+        | Bytecode::GetField(index)
+        | Bytecode::SetField(index)
+        | Bytecode::CheckCast(index)
+        | Bytecode::InstanceOf(index) => {
+            match module.and_then(|module| describe_constant(module, *index)) {
+                Some(value) => format!("{} #{} ; {}", name, index, value),
+                None => format!("{} #{}", name, index),
+            }
+        }
+        Bytecode::InvokeVirtual(index, arguments)
+        | Bytecode::InvokeSpecial(index, arguments)
+        | Bytecode::InvokeInterface(index, arguments) => {
+            match module.and_then(|module| describe_constant(module, *index)) {
+                Some(value) => format!("{} #{} {} ; {}", name, index, arguments, value),
+                None => format!("{} #{} {}", name, index, arguments),
+            }
+        }
+        Bytecode::Store(index) | Bytecode::LoadLocal(index) => format!("{} {}", name, index),
+        Bytecode::Jump(offset) | Bytecode::JumpIfTrue(offset) | Bytecode::JumpIfFalse(offset) => {
+            format!("{} {:04}", name, offset)
+        }
+        _ => name.to_string(),
+    }
+}
+
+// 反汇编一段函数体字节码，每行一条指令：偏移 助记符 操作数 ; 常量值
+pub fn disassemble(code: &[u8], module: Option<&Module>) -> Result<String, String> {
+    let mut reader = BytecodeReader::new(code.to_vec());
+    let mut output = String::new();
+    while reader.has_next() {
+        let offset = reader.position;
+        let bytecode = reader.read_bytecode()
+            .map_err(|error| format!("Invalid instruction at offset {:04}: {}", offset, error))?;
+        writeln!(output, "{:04} {}", offset, describe_bytecode(&bytecode, module)).unwrap();
+    }
+    Ok(output)
+}
+
+pub fn disassemble_module(module: &Module) -> Result<String, String> {
+    let mut output = String::new();
+    if let Some(source_file) = &module.source_file {
+        writeln!(output, "source {:?}", source_file).unwrap();
+    }
+    if !module.package.is_empty() {
+        writeln!(output, "package {}", module.package).unwrap();
+    }
+    writeln!(output).unwrap();
+    writeln!(output, "constants:").unwrap();
+    for (index, constant) in module.constants.iter().enumerate() {
+        match constant {
+            Constant::Class(_) | Constant::Function { .. } => {
+                let value = describe_constant(module, index).unwrap_or_else(|| "?".to_string());
+                writeln!(output, "    #{} = {} ; {}", index, constant, value).unwrap();
+            }
+            _ => writeln!(output, "    #{} = {}", index, constant).unwrap(),
+        }
+    }
+    let get_name = |index: usize| module.get_string(index).unwrap_or("?").to_string();
+    for class in &module.classes {
+        writeln!(output).unwrap();
+        write!(output, "class {}", get_name(class.name)).unwrap();
+        if let Some(super_class) = class.super_class {
+            write!(output, " : {}", get_name(super_class)).unwrap();
+        }
+        for interface in &class.interfaces {
+            write!(output, ", {}", get_name(*interface)).unwrap();
+        }
+        writeln!(output).unwrap();
+        for field in &class.fields {
+            writeln!(output, "    field {}", get_name(*field)).unwrap();
+        }
+        for method in &class.methods {
+            let name = module.functions.get(*method).map(|function| get_name(function.name));
+            writeln!(output, "    method {}", name.unwrap_or_else(|| "?".to_string())).unwrap();
+        }
+    }
+    if !module.natives.is_empty() {
+        writeln!(output).unwrap();
+    }
+    for native in &module.natives {
+        match describe_constant(module, *native) {
+            Some(value) => writeln!(output, "native #{} ; {}", native, value).unwrap(),
+            None => writeln!(output, "native #{}", native).unwrap(),
+        }
+    }
+    for function in &module.functions {
+        writeln!(output).unwrap();
+        writeln!(
+            output, "{}fn {} (parameters: {}, locals: {})",
+            if function.operator { "operator " } else { "" }, get_name(function.name), function.parameters, function.locals
+        ).unwrap();
+        let listing = disassemble(&function.code, Some(module))
+            .map_err(|error| format!("{} in function '{}'", error, get_name(function.name)))?;
+        for line in listing.lines() {
+            writeln!(output, "    {}", line).unwrap();
+        }
+        for line in &function.lines {
+            writeln!(output, "    line {:04} {}:{}", line.offset, line.line, line.column).unwrap();
+        }
+        for handler in &function.handlers {
+            write!(output, "    catch {:04} {:04} {:04}", handler.start, handler.end, handler.handler).unwrap();
+            match handler.class.map(|class| (class, describe_constant(module, class))) {
+                Some((class, Some(name))) => writeln!(output, " #{} ; {}", class, name).unwrap(),
+                Some((class, None)) => writeln!(output, " #{}", class).unwrap(),
+                None => writeln!(output).unwrap(),
+            }
+        }
+        for variable in &function.variables {
+            write!(
+                output, "    local {:04} {:04} {} #{} #{}", variable.start, variable.end, variable.slot, variable.name, variable.value_type
+            ).unwrap();
+            match (module.get_string(variable.name), module.get_string(variable.value_type)) {
+                (Some(name), Some(value_type)) => writeln!(output, " ; {}: {}", name, value_type).unwrap(),
+                _ => writeln!(output).unwrap(),
+            }
+        }
+    }
+    Ok(output)
+}
@@ -0,0 +1,79 @@
+use std::fmt::{Debug, Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeErrorKind {
+    UnexpectedEnd, // 输入提前结束
+    UnknownOpcode(u8),
+    UnknownConstantTag(u8),
+    InvalidUtf8,
+    InvalidChar, // 字符常量不是恰好一个字符
+    IntegerOverflow, // 变长整数过长或超出 usize
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    InvalidJumpTarget(usize), // 跳转目标不在指令边界上
+    UnexpectedConstant, // 常量段中出现了非常量指令
+    SectionLengthMismatch, // 段的内容与声明的长度不一致
+    CodeCountMismatch, // 函数体个数与函数表不一致
+    InvalidManifest, // 归档清单的包索引与模块不一致
+    VersionMismatch(u16), // 归档中模块的格式版本与清单不一致，参数是模块的版本
+    ChecksumMismatch(u8), // 段的内容与校验和不一致，参数是段的编号
+    Io(std::io::ErrorKind), // 读取输入流失败
+}
+
+impl Display for DecodeErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeErrorKind::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeErrorKind::UnknownOpcode(code) => write!(f, "unknown opcode 0x{:02X}", code),
+            DecodeErrorKind::UnknownConstantTag(tag) => write!(f, "unknown constant tag 0x{:02X}", tag),
+            DecodeErrorKind::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            DecodeErrorKind::InvalidChar => write!(f, "invalid character"),
+            DecodeErrorKind::IntegerOverflow => write!(f, "integer overflow"),
+            DecodeErrorKind::InvalidMagic => write!(f, "invalid magic number"),
+            DecodeErrorKind::UnsupportedVersion(version) => write!(f, "unsupported format version {}", version),
+            DecodeErrorKind::InvalidJumpTarget(target) => write!(f, "jump target {:04} is not an instruction boundary", target),
+            DecodeErrorKind::UnexpectedConstant => write!(f, "expected a constant"),
+            DecodeErrorKind::SectionLengthMismatch => write!(f, "section length mismatch"),
+            DecodeErrorKind::CodeCountMismatch => write!(f, "code section does not match the function table"),
+            DecodeErrorKind::InvalidManifest => write!(f, "package index does not match the archived modules"),
+            DecodeErrorKind::VersionMismatch(version) => write!(f, "module format version {} does not match the manifest", version),
+            DecodeErrorKind::ChecksumMismatch(section) => write!(f, "checksum mismatch in section 0x{:02X}", section),
+            DecodeErrorKind::Io(kind) => write!(f, "I/O error ({})", kind),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct DecodeError {
+    pub offset: usize, // 出错位置的字节偏移
+    pub expected: &'static str, // 正在读取的内容
+    pub kind: DecodeErrorKind,
+}
+
+impl DecodeError {
+    pub fn new(offset: usize, expected: &'static str, kind: DecodeErrorKind) -> Self {
+        DecodeError { offset, expected, kind }
+    }
+
+    // 把相对于某段数据的偏移换算为相对于整个输入的偏移
+    pub fn relocate(mut self, base: usize) -> Self {
+        self.offset += base;
+        self
+    }
+}
+
+impl Debug for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DecodeError: {} at offset {:04} while reading {}", self.kind, self.offset, self.expected)
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at offset {:04} while reading {}", self.kind, self.offset, self.expected)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub type DecodeResult<T> = Result<T, DecodeError>;
@@ -1,3 +1,12 @@
 pub mod bytecode;
 pub mod builder;
-pub mod reader;
\ No newline at end of file
+pub mod reader;
+pub mod module;
+pub mod constant;
+pub mod error;
+pub mod disassembler;
+pub mod assembler;
+pub mod verifier;
+pub mod optimizer;
+pub mod archive;
+pub mod checksum;
\ No newline at end of file
@@ -0,0 +1,432 @@
+use crate::bytecode::builder::BytecodeBuilder;
+use crate::bytecode::bytecode::Bytecode;
+use crate::bytecode::checksum::crc32;
+use crate::bytecode::constant::Constant;
+use crate::bytecode::error::{DecodeError, DecodeErrorKind, DecodeResult};
+use crate::bytecode::reader::BytecodeReader;
+
+pub const MAGIC: [u8; 4] = *b"LMBD";
+pub const FORMAT_VERSION: u16 = 5;
+pub const MIN_FORMAT_VERSION: u16 = 2; // 版本 1 的常量池只有字符串
+pub const VARINT_FORMAT_VERSION: u16 = 3; // 从这个版本开始索引、偏移和长度使用变长编码
+pub const CHECKSUM_FORMAT_VERSION: u16 = 4; // 从这个版本开始每个段的长度之后是内容的 CRC-32
+pub const OPERATOR_FORMAT_VERSION: u16 = 5; // 从这个版本开始函数表记录函数是否是 operator fn
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Section {
+    Metadata = 0x01, // 源文件和包名
+    Constants = 0x02, // 常量池
+    Classes = 0x03, // 类表
+    Functions = 0x04, // 函数表
+    Code = 0x05, // 函数体字节码
+    LineNumbers = 0x06, // 调试信息：指令偏移对应的源码位置
+    ExceptionTables = 0x07, // 异常处理表
+    LocalVariables = 0x08, // 调试信息：局部变量的名称、类型和有效范围
+    Natives = 0x09, // 本地函数声明
+}
+
+impl Section {
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0x01 => Some(Section::Metadata),
+            0x02 => Some(Section::Constants),
+            0x03 => Some(Section::Classes),
+            0x04 => Some(Section::Functions),
+            0x05 => Some(Section::Code),
+            0x06 => Some(Section::LineNumbers),
+            0x07 => Some(Section::ExceptionTables),
+            0x08 => Some(Section::LocalVariables),
+            0x09 => Some(Section::Natives),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FunctionDefinition {
+    pub name: usize, // #index: 函数名在常量池中的索引
+    pub parameters: usize, // 参数个数
+    pub locals: usize, // 局部变量个数（包含参数）
+    pub operator: bool, // 由 operator fn 声明，运算符指令只会回退到这样的方法
+    pub code: Vec<u8>, // 指令字节码
+    pub lines: Vec<LineNumber>, // 按偏移排序，每一项覆盖到下一项之前的指令
+    pub handlers: Vec<ExceptionHandler>, // 按优先级排序，内层的 try 在前
+    pub variables: Vec<LocalVariable>, // 按声明顺序排列
+}
+
+// [start, end) 范围内的指令抛出异常时跳转到 handler，此时操作数栈只有被抛出的值。
+// 只有 Throw 抛出的值会匹配处理器，虚拟机自身的错误（除以零、空指针、类型转换失败等）不能被捕获
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ExceptionHandler {
+    pub start: usize, // 字节偏移，包含
+    pub end: usize, // 字节偏移，不包含
+    pub handler: usize, // 处理代码的字节偏移
+    pub class: Option<usize>, // #index: 捕获的类在常量池中的索引，None 表示捕获所有异常
+}
+
+impl ExceptionHandler {
+    pub fn write(&self, builder: &mut BytecodeBuilder) {
+        builder.write_usize(self.start);
+        builder.write_usize(self.end);
+        builder.write_usize(self.handler);
+        builder.write_bool(self.class.is_some());
+        if let Some(class) = self.class {
+            builder.write_usize(class);
+        }
+    }
+
+    pub fn read(reader: &mut BytecodeReader) -> DecodeResult<Self> {
+        let start = reader.read_usize()?;
+        let end = reader.read_usize()?;
+        let handler = reader.read_usize()?;
+        let class = if reader.read_bool()? { Some(reader.read_usize()?) } else { None };
+        Ok(ExceptionHandler { start, end, handler, class })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct LineNumber {
+    pub offset: usize, // 指令在函数体中的字节偏移
+    pub line: usize, // 从 1 开始
+    pub column: usize, // 从 1 开始
+}
+
+impl LineNumber {
+    pub fn write(&self, builder: &mut BytecodeBuilder) {
+        builder.write_usize(self.offset);
+        builder.write_usize(self.line);
+        builder.write_usize(self.column);
+    }
+
+    pub fn read(reader: &mut BytecodeReader) -> DecodeResult<Self> {
+        Ok(LineNumber { offset: reader.read_usize()?, line: reader.read_usize()?, column: reader.read_usize()? })
+    }
+}
+
+// 局部变量在 [start, end) 范围内有效
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct LocalVariable {
+    pub name: usize, // #index: 变量名在常量池中的索引
+    pub value_type: usize, // #index: 声明类型在常量池中的索引，写法与签名中的类型相同
+    pub slot: usize, // 局部变量下标
+    pub start: usize, // 字节偏移，包含
+    pub end: usize, // 字节偏移，不包含
+}
+
+impl LocalVariable {
+    pub fn write(&self, builder: &mut BytecodeBuilder) {
+        builder.write_usize(self.name);
+        builder.write_usize(self.value_type);
+        builder.write_usize(self.slot);
+        builder.write_usize(self.start);
+        builder.write_usize(self.end);
+    }
+
+    pub fn read(reader: &mut BytecodeReader) -> DecodeResult<Self> {
+        Ok(LocalVariable {
+            name: reader.read_usize()?,
+            value_type: reader.read_usize()?,
+            slot: reader.read_usize()?,
+            start: reader.read_usize()?,
+            end: reader.read_usize()?,
+        })
+    }
+}
+
+impl FunctionDefinition {
+    // 查找覆盖指定偏移的源码位置
+    pub fn get_line(&self, offset: usize) -> Option<&LineNumber> {
+        get_line(&self.lines, offset)
+    }
+
+    // 在指定偏移处有效的局部变量
+    pub fn get_variables(&self, offset: usize) -> impl Iterator<Item = &LocalVariable> {
+        self.variables.iter().filter(move |variable| variable.start <= offset && offset < variable.end)
+    }
+}
+
+pub fn get_line(lines: &[LineNumber], offset: usize) -> Option<&LineNumber> {
+    let index = lines.partition_point(|line| line.offset <= offset);
+    if index == 0 { None } else { lines.get(index - 1) }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClassDefinition {
+    pub name: usize, // #index: 类名在常量池中的索引
+    pub super_class: Option<usize>, // #index: 父类名在常量池中的索引
+    pub interfaces: Vec<usize>, // #index: 接口名在常量池中的索引
+    pub fields: Vec<usize>, // #index: 字段名在常量池中的索引
+    pub methods: Vec<usize>, // 方法在函数表中的下标
+}
+
+// 在不同格式版本之间转换函数，跳转偏移、行号表和异常表中的偏移按新的编码长度重新计算
+pub fn transcode(function: &FunctionDefinition, from: u16, to: u16) -> DecodeResult<FunctionDefinition> {
+    if from == to {
+        return Ok(function.clone());
+    }
+    let (instructions, offsets) = BytecodeReader::with_version(function.code.clone(), from).read_instructions()?;
+    let mut builder = BytecodeBuilder::with_version(to);
+    let relocated = builder.write_instructions(&instructions);
+    // 落在指令中间的偏移归到该指令
+    let relocate = |offset: usize| relocated[offsets.partition_point(|start| *start <= offset).saturating_sub(1)];
+    Ok(FunctionDefinition {
+        code: builder.bytes,
+        lines: function.lines.iter().map(|line| LineNumber { offset: relocate(line.offset), ..*line }).collect(),
+        handlers: function.handlers.iter().map(|handler| ExceptionHandler {
+            start: relocate(handler.start),
+            end: relocate(handler.end),
+            handler: relocate(handler.handler),
+            class: handler.class,
+        }).collect(),
+        variables: function.variables.iter().map(|variable| LocalVariable {
+            start: relocate(variable.start),
+            end: relocate(variable.end),
+            ..*variable
+        }).collect(),
+        ..function.clone()
+    })
+}
+
+impl ClassDefinition {
+    pub fn write(&self, builder: &mut BytecodeBuilder) {
+        builder.write_usize(self.name);
+        builder.write_bool(self.super_class.is_some());
+        if let Some(super_class) = self.super_class {
+            builder.write_usize(super_class);
+        }
+        builder.write_vec(&self.interfaces, |builder, index| builder.write_usize(*index));
+        builder.write_vec(&self.fields, |builder, index| builder.write_usize(*index));
+        builder.write_vec(&self.methods, |builder, index| builder.write_usize(*index));
+    }
+
+    pub fn read(reader: &mut BytecodeReader) -> DecodeResult<Self> {
+        let name = reader.read_usize()?;
+        let super_class = if reader.read_bool()? { Some(reader.read_usize()?) } else { None };
+        let interfaces = reader.read_vec(|reader| reader.read_usize())?;
+        let fields = reader.read_vec(|reader| reader.read_usize())?;
+        let methods = reader.read_vec(|reader| reader.read_usize())?;
+        Ok(ClassDefinition { name, super_class, interfaces, fields, methods })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Module {
+    pub source_file: Option<String>,
+    pub package: String,
+    pub constants: Vec<Constant>, // 常量池
+    pub classes: Vec<ClassDefinition>,
+    pub functions: Vec<FunctionDefinition>,
+    pub natives: Vec<usize>, // #index: 本地函数的函数引用在常量池中的索引，由宿主在加载时绑定
+}
+
+impl Module {
+    pub fn new() -> Self { Module::default() }
+
+    pub fn get_constant(&self, index: usize) -> Option<&Constant> { self.constants.get(index) }
+
+    pub fn get_string(&self, index: usize) -> Option<&str> { self.get_constant(index)?.as_str() }
+
+    pub fn add_constant(&mut self, value: Constant) -> usize {
+        if let Some(index) = self.constants.iter().position(|constant| *constant == value) {
+            return index;
+        }
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    pub fn add_string(&mut self, value: &str) -> usize { self.add_constant(Constant::String(value.to_string())) }
+
+    pub fn add_class(&mut self, name: &str) -> usize {
+        let name = self.add_string(name);
+        self.add_constant(Constant::Class(name))
+    }
+
+    pub fn add_function(&mut self, name: &str, signature: &str) -> usize {
+        let name = self.add_string(name);
+        let signature = self.add_string(signature);
+        self.add_constant(Constant::Function { name, signature })
+    }
+
+    fn write_section<F>(builder: &mut BytecodeBuilder, section: Section, write_payload: F)
+    where
+        F: Fn(&mut BytecodeBuilder),
+    {
+        let mut payload = BytecodeBuilder::with_version(builder.version);
+        write_payload(&mut payload);
+        builder.write_u8(section as u8);
+        builder.write_usize(payload.bytes.len());
+        if builder.version >= CHECKSUM_FORMAT_VERSION {
+            builder.write_u32(crc32(&payload.bytes));
+        }
+        builder.write_bytes(&payload.bytes);
+    }
+
+    // 按 builder 的格式版本输出，默认是当前版本。
+    // 函数体需要转换到旧版本而无法解码时报错，此时不输出任何内容
+    pub fn write(&self, builder: &mut BytecodeBuilder) -> DecodeResult<()> {
+        let functions: Vec<FunctionDefinition> = self.functions.iter()
+            .map(|function| transcode(function, FORMAT_VERSION, builder.version))
+            .collect::<DecodeResult<_>>()?;
+        if builder.version < OPERATOR_FORMAT_VERSION && self.functions.iter().any(|function| function.operator) {
+            return Err(DecodeError::new(0, "operator function", DecodeErrorKind::UnsupportedVersion(builder.version)));
+        }
+        builder.write_bytes(&MAGIC);
+        builder.write_u16(builder.version);
+        builder.write_usize(9); // 段数
+        Self::write_section(builder, Section::Metadata, |builder| {
+            builder.write_bool(self.source_file.is_some());
+            if let Some(source_file) = &self.source_file {
+                builder.write_string(source_file);
+            }
+            builder.write_string(&self.package);
+        });
+        Self::write_section(builder, Section::Constants, |builder| {
+            builder.write_vec(&self.constants, |builder, constant| {
+                builder.write_bytecode(Bytecode::Constant(constant.clone()));
+            });
+        });
+        Self::write_section(builder, Section::Classes, |builder| {
+            builder.write_vec(&self.classes, |builder, class| class.write(builder));
+        });
+        Self::write_section(builder, Section::Functions, |builder| {
+            builder.write_vec(&self.functions, |builder, function| {
+                builder.write_usize(function.name);
+                builder.write_usize(function.parameters);
+                builder.write_usize(function.locals);
+                if builder.version >= OPERATOR_FORMAT_VERSION {
+                    builder.write_bool(function.operator);
+                }
+            });
+        });
+        Self::write_section(builder, Section::Code, |builder| {
+            builder.write_vec(&functions, |builder, function| {
+                builder.write_usize(function.code.len());
+                builder.write_bytes(&function.code);
+            });
+        });
+        Self::write_section(builder, Section::LineNumbers, |builder| {
+            builder.write_vec(&functions, |builder, function| {
+                builder.write_vec(&function.lines, |builder, line| line.write(builder));
+            });
+        });
+        Self::write_section(builder, Section::ExceptionTables, |builder| {
+            builder.write_vec(&functions, |builder, function| {
+                builder.write_vec(&function.handlers, |builder, handler| handler.write(builder));
+            });
+        });
+        Self::write_section(builder, Section::LocalVariables, |builder| {
+            builder.write_vec(&functions, |builder, function| {
+                builder.write_vec(&function.variables, |builder, variable| variable.write(builder));
+            });
+        });
+        Self::write_section(builder, Section::Natives, |builder| {
+            builder.write_vec(&self.natives, |builder, index| builder.write_usize(*index));
+        });
+        Ok(())
+    }
+
+    pub fn read(reader: &mut BytecodeReader) -> DecodeResult<Self> {
+        let start = reader.position;
+        let magic = reader.read_bytes(MAGIC.len())?;
+        if magic != MAGIC {
+            return Err(DecodeError::new(start, "module header", DecodeErrorKind::InvalidMagic));
+        }
+        let version_offset = reader.position;
+        let version = reader.read_u16()?;
+        if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+            return Err(DecodeError::new(version_offset, "module header", DecodeErrorKind::UnsupportedVersion(version)));
+        }
+        reader.version = version;
+        let mut module = Module::new();
+        let mut codes: Vec<(usize, Vec<u8>)> = Vec::new(); // (函数体在输入中的偏移, 函数体)
+        let mut lines: Vec<Vec<LineNumber>> = Vec::new();
+        let mut handlers: Vec<Vec<ExceptionHandler>> = Vec::new();
+        let mut variables: Vec<Vec<LocalVariable>> = Vec::new();
+        let count = reader.read_usize()?;
+        for _ in 0..count {
+            let code = reader.read_u8()?;
+            let length = reader.read_usize()?;
+            let checksum = if version >= CHECKSUM_FORMAT_VERSION { Some(reader.read_u32()?) } else { None };
+            let base = reader.position;
+            let payload = reader.read_bytes(length)?;
+            // 未知的段也要校验，损坏的数据可能恰好改写了段的编号
+            if let Some(checksum) = checksum && crc32(&payload) != checksum {
+                return Err(DecodeError::new(base, "section", DecodeErrorKind::ChecksumMismatch(code)));
+            }
+            let Some(section) = Section::from_code(code) else {
+                continue; // 跳过未知的段
+            };
+            let mut payload = BytecodeReader::with_version(payload, version);
+            let result: DecodeResult<()> = (|| {
+                match section {
+                    Section::Metadata => {
+                        module.source_file = if payload.read_bool()? { Some(payload.read_string()?) } else { None };
+                        module.package = payload.read_string()?;
+                    }
+                    Section::Constants => {
+                        module.constants = payload.read_vec(|reader| {
+                            let start = reader.position;
+                            match reader.read_bytecode()? {
+                                Bytecode::Constant(value) => Ok(value),
+                                _ => Err(DecodeError::new(start, "constant", DecodeErrorKind::UnexpectedConstant)),
+                            }
+                        })?;
+                    }
+                    Section::Classes => {
+                        module.classes = payload.read_vec(ClassDefinition::read)?;
+                    }
+                    Section::Functions => {
+                        module.functions = payload.read_vec(|reader| Ok(FunctionDefinition {
+                            name: reader.read_usize()?,
+                            parameters: reader.read_usize()?,
+                            locals: reader.read_usize()?,
+                            operator: reader.version >= OPERATOR_FORMAT_VERSION && reader.read_bool()?,
+                            ..FunctionDefinition::default()
+                        }))?;
+                    }
+                    Section::Code => {
+                        codes = payload.read_vec(|reader| {
+                            let length = reader.read_usize()?;
+                            Ok((base + reader.position, reader.read_bytes(length)?))
+                        })?;
+                    }
+                    Section::LineNumbers => {
+                        lines = payload.read_vec(|reader| reader.read_vec(LineNumber::read))?;
+                    }
+                    Section::ExceptionTables => {
+                        handlers = payload.read_vec(|reader| reader.read_vec(ExceptionHandler::read))?;
+                    }
+                    Section::LocalVariables => {
+                        variables = payload.read_vec(|reader| reader.read_vec(LocalVariable::read))?;
+                    }
+                    Section::Natives => {
+                        module.natives = payload.read_vec(|reader| reader.read_usize())?;
+                    }
+                }
+                Ok(())
+            })();
+            result.map_err(|error| error.relocate(base))?;
+        }
+        let count = codes.len();
+        if count != module.functions.len()
+            || !(lines.is_empty() || lines.len() == count)
+            || !(handlers.is_empty() || handlers.len() == count)
+            || !(variables.is_empty() || variables.len() == count) {
+            return Err(DecodeError::new(reader.position, "module", DecodeErrorKind::CodeCountMismatch));
+        }
+        // 行号表、异常表和局部变量表是可选的
+        lines.resize(count, Vec::new());
+        handlers.resize(count, Vec::new());
+        variables.resize(count, Vec::new());
+        let tables = lines.into_iter().zip(handlers).zip(variables);
+        for ((function, (offset, code)), ((lines, handlers), variables)) in module.functions.iter_mut().zip(codes).zip(tables) {
+            function.code = code;
+            function.lines = lines;
+            function.handlers = handlers;
+            function.variables = variables;
+            // 旧版本的函数转换为当前版本，之后的解码都按当前版本进行
+            *function = transcode(function, version, FORMAT_VERSION).map_err(|error| error.relocate(offset))?;
+        }
+        Ok(module)
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::HashSet;
+use crate::bytecode::builder::BytecodeBuilder;
+use crate::bytecode::bytecode::Bytecode;
+use crate::bytecode::constant::Constant;
+use crate::bytecode::error::DecodeResult;
+use crate::bytecode::module::{ExceptionHandler, FunctionDefinition, LineNumber, LocalVariable, Module};
+use crate::bytecode::reader::BytecodeReader;
+
+// 待优化的函数体，跳转目标、行号表、异常表和局部变量表中的位置都是指令下标
+#[derive(Debug, Clone, Default)]
+pub struct Code {
+    pub instructions: Vec<Bytecode>,
+    pub lines: Vec<(usize, usize, usize)>, // (指令下标, 行, 列)
+    pub handlers: Vec<ExceptionHandler>,
+    pub variables: Vec<LocalVariable>,
+}
+
+// 反复执行各个优化，直到代码不再变化。新的常量加入模块的常量池
+pub fn optimize(module: &mut Module, mut code: Code) -> Code {
+    loop {
+        fold_constants(module, &mut code);
+        thread_jumps(&mut code);
+        let mut removed = remove_redundant(&code);
+        for (index, reachable) in get_reachable(&code).into_iter().enumerate() {
+            removed[index] |= !reachable;
+        }
+        if !removed.contains(&true) {
+            return code;
+        }
+        code = relocate(code, &removed);
+    }
+}
+
+// 解码函数体后优化，再重新编码并换算偏移
+pub fn optimize_function(module: &mut Module, function: &FunctionDefinition) -> DecodeResult<FunctionDefinition> {
+    let (instructions, offsets) = BytecodeReader::new(function.code.clone()).read_instructions()?;
+    let get_index = |offset: usize| offsets.partition_point(|start| *start < offset);
+    let code = Code {
+        instructions,
+        lines: function.lines.iter().map(|line| (get_index(line.offset), line.line, line.column)).collect(),
+        handlers: function.handlers.iter().map(|handler| ExceptionHandler {
+            start: get_index(handler.start),
+            end: get_index(handler.end),
+            handler: get_index(handler.handler),
+            class: handler.class,
+        }).collect(),
+        variables: function.variables.iter().map(|variable| LocalVariable {
+            start: get_index(variable.start),
+            end: get_index(variable.end),
+            ..*variable
+        }).collect(),
+    };
+    let code = optimize(module, code);
+    let mut builder = BytecodeBuilder::new();
+    let offsets = builder.write_instructions(&code.instructions);
+    Ok(FunctionDefinition {
+        code: builder.bytes,
+        lines: code.lines.iter().map(|(index, line, column)| LineNumber { offset: offsets[*index], line: *line, column: *column }).collect(),
+        handlers: code.handlers.iter().map(|handler| ExceptionHandler {
+            start: offsets[handler.start],
+            end: offsets[handler.end],
+            handler: offsets[handler.handler],
+            class: handler.class,
+        }).collect(),
+        variables: code.variables.iter().map(|variable| LocalVariable {
+            start: offsets[variable.start],
+            end: offsets[variable.end],
+            ..*variable
+        }).collect(),
+        ..function.clone()
+    })
+}
+
+// 跳转目标和异常表中的位置，窥孔优化不能跨过这些位置
+fn get_leaders(code: &Code) -> HashSet<usize> {
+    let mut leaders: HashSet<usize> = code.instructions.iter().filter_map(Bytecode::get_jump_target).collect();
+    for handler in &code.handlers {
+        leaders.extend([handler.start, handler.end, handler.handler]);
+    }
+    leaders
+}
+
+// 常量的运算替换为结果常量，被合并的指令改为 Nop，删除后下一轮可以继续折叠
+fn fold_constants(module: &mut Module, code: &mut Code) {
+    let leaders = get_leaders(code);
+    let instructions = &mut code.instructions;
+    let mut index = 0;
+    while index < instructions.len() {
+        let folded = match instructions[index..] {
+            [Bytecode::LoadConst(left), Bytecode::LoadConst(right), ref operator, ..]
+                if !leaders.contains(&(index + 1)) && !leaders.contains(&(index + 2)) => {
+                let (left, right) = (module.get_constant(left), module.get_constant(right));
+                left.zip(right).and_then(|(left, right)| fold_binary(operator, left, right)).map(|value| (value, 3))
+            }
+            [Bytecode::LoadConst(operand), ref operator, ..] if !leaders.contains(&(index + 1)) => {
+                module.get_constant(operand).and_then(|operand| fold_unary(operator, operand)).map(|value| (value, 2))
+            }
+            _ => None,
+        };
+        match folded {
+            Some((value, length)) => {
+                instructions[index] = Bytecode::LoadConst(add_folded_constant(module, value));
+                instructions[index + 1..index + length].fill(Bytecode::Nop);
+                index += length;
+            }
+            None => index += 1,
+        }
+    }
+}
+
+// 浮点数按位比较，避免 0.0 与 -0.0 被合并
+fn add_folded_constant(module: &mut Module, value: Constant) -> usize {
+    match value {
+        Constant::Float(value) => {
+            let existing = module.constants.iter().position(|constant| {
+                matches!(constant, Constant::Float(other) if other.to_bits() == value.to_bits())
+            });
+            existing.unwrap_or_else(|| {
+                module.constants.push(Constant::Float(value));
+                module.constants.len() - 1
+            })
+        }
+        value => module.add_constant(value),
+    }
+}
+
+// 只折叠结果与虚拟机运行时完全一致的情况：溢出、除以零等留到运行时处理
+fn fold_binary(operator: &Bytecode, left: &Constant, right: &Constant) -> Option<Constant> {
+    match (left, right) {
+        (Constant::Int(left), Constant::Int(right)) => {
+            let (left, right) = (*left, *right);
+            let value = match operator {
+                Bytecode::Add => left.checked_add(right),
+                Bytecode::Sub => left.checked_sub(right),
+                Bytecode::Mul => left.checked_mul(right),
+                Bytecode::Div => left.checked_div(right),
+                Bytecode::Mod => left.checked_rem(right),
+                Bytecode::Pow => u32::try_from(right).ok().and_then(|right| left.checked_pow(right)),
+                Bytecode::And => Some(left & right),
+                Bytecode::Or => Some(left | right),
+                _ => None,
+            };
+            value.map(Constant::Int)
+        }
+        (Constant::Int(_) | Constant::Float(_), Constant::Int(_) | Constant::Float(_)) => {
+            let (left, right) = (get_float(left)?, get_float(right)?);
+            let value = match operator {
+                Bytecode::Add => left + right,
+                Bytecode::Sub => left - right,
+                Bytecode::Mul => left * right,
+                Bytecode::Div => left / right,
+                Bytecode::Mod => left % right,
+                Bytecode::Pow => left.powf(right),
+                _ => return None,
+            };
+            Some(Constant::Float(value))
+        }
+        (Constant::String(left), Constant::String(right)) if matches!(operator, Bytecode::Add) => {
+            Some(Constant::String(format!("{}{}", left, right)))
+        }
+        (Constant::String(left), Constant::Char(right)) if matches!(operator, Bytecode::Add) => {
+            Some(Constant::String(format!("{}{}", left, right)))
+        }
+        (Constant::String(left), Constant::Int(right)) if matches!(operator, Bytecode::Add) => {
+            Some(Constant::String(format!("{}{}", left, right)))
+        }
+        _ => None,
+    }
+}
+
+fn fold_unary(operator: &Bytecode, operand: &Constant) -> Option<Constant> {
+    match (operator, operand) {
+        (Bytecode::Neg, Constant::Int(value)) => value.checked_neg().map(Constant::Int),
+        (Bytecode::Neg, Constant::Float(value)) => Some(Constant::Float(-value)),
+        (Bytecode::Pos, Constant::Int(_) | Constant::Float(_)) => Some(operand.clone()),
+        _ => None,
+    }
+}
+
+fn get_float(constant: &Constant) -> Option<f64> {
+    match constant {
+        Constant::Int(value) => Some(*value as f64),
+        Constant::Float(value) => Some(*value),
+        _ => None,
+    }
+}
+
+// 跳转到无条件跳转时直接跳到最终目标
+fn thread_jumps(code: &mut Code) {
+    let instructions = &code.instructions;
+    let threaded: Vec<Bytecode> = instructions.iter().map(|instruction| {
+        let Some(mut target) = instruction.get_jump_target() else {
+            return instruction.clone();
+        };
+        let mut visited = HashSet::new();
+        while let Some(Bytecode::Jump(next)) = instructions.get(target) {
+            if !visited.insert(target) {
+                break; // 死循环
+            }
+            target = *next;
+        }
+        instruction.with_jump_target(target)
+    }).collect();
+    code.instructions = threaded;
+}
+
+// 标记可以直接删除的指令：Nop、相邻的 Dup 和 Pop、跳到下一条指令的 Jump
+fn remove_redundant(code: &Code) -> Vec<bool> {
+    let leaders = get_leaders(code);
+    let instructions = &code.instructions;
+    let mut removed = vec![false; instructions.len()];
+    let mut index = 0;
+    while index < instructions.len() {
+        match instructions[index..] {
+            [Bytecode::Nop, ..] => removed[index] = true,
+            [Bytecode::Dup, Bytecode::Pop, ..] if !leaders.contains(&(index + 1)) => {
+                removed[index] = true;
+                removed[index + 1] = true;
+                index += 1;
+            }
+            [Bytecode::Jump(target), ..] if target == index + 1 => removed[index] = true,
+            _ => {}
+        }
+        index += 1;
+    }
+    removed
+}
+
+// 从入口开始沿控制流标记可达的指令；保护范围内有可达指令时，异常处理代码也可达
+fn get_reachable(code: &Code) -> Vec<bool> {
+    let instructions = &code.instructions;
+    let mut reachable = vec![false; instructions.len()];
+    let mut pending = vec![0];
+    loop {
+        while let Some(index) = pending.pop() {
+            if index >= instructions.len() || reachable[index] {
+                continue;
+            }
+            reachable[index] = true;
+            let instruction = &instructions[index];
+            if let Some(target) = instruction.get_jump_target() {
+                pending.push(target);
+            }
+            if !matches!(instruction, Bytecode::Jump(_) | Bytecode::Return | Bytecode::Throw) {
+                pending.push(index + 1);
+            }
+        }
+        pending.extend(code.handlers.iter()
+            .filter(|handler| reachable.get(handler.handler) == Some(&false))
+            .filter(|handler| reachable.get(handler.start..handler.end).is_some_and(|range| range.contains(&true)))
+            .map(|handler| handler.handler));
+        if pending.is_empty() {
+            return reachable;
+        }
+    }
+}
+
+// 删除标记的指令，跳转目标、行号和异常表中指向被删除指令的位置改为其后第一条保留的指令
+fn relocate(code: Code, removed: &[bool]) -> Code {
+    let mut indices = Vec::with_capacity(removed.len() + 1);
+    let mut kept = 0;
+    for removed in removed {
+        indices.push(kept);
+        if !removed {
+            kept += 1;
+        }
+    }
+    indices.push(kept);
+    let instructions = code.instructions.iter().zip(removed)
+        .filter(|(_, removed)| !**removed)
+        .map(|(instruction, _)| match instruction.get_jump_target() {
+            Some(target) => instruction.with_jump_target(indices[target]),
+            None => instruction.clone(),
+        })
+        .collect();
+    // 同一条指令上有多个位置时保留最后一个
+    let mut lines: Vec<(usize, usize, usize)> = Vec::new();
+    for (index, line, column) in code.lines {
+        let index = indices[index];
+        if index == kept {
+            continue;
+        }
+        if lines.last().is_some_and(|last| last.0 == index) {
+            lines.pop();
+        }
+        if lines.last().is_none_or(|last| (last.1, last.2) != (line, column)) {
+            lines.push((index, line, column));
+        }
+    }
+    let handlers = code.handlers.iter()
+        .map(|handler| ExceptionHandler {
+            start: indices[handler.start],
+            end: indices[handler.end],
+            handler: indices[handler.handler],
+            class: handler.class,
+        })
+        .filter(|handler| handler.start < handler.end) // 保护范围内的指令都已删除
+        .collect();
+    let variables = code.variables.iter()
+        .map(|variable| LocalVariable { start: indices[variable.start], end: indices[variable.end], ..*variable })
+        .filter(|variable| variable.start < variable.end)
+        .collect();
+    Code { instructions, lines, handlers, variables }
+}
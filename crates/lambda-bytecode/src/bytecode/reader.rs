@@ -1,188 +1,247 @@
-use std::str::FromStr;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
 use bigdecimal::BigDecimal;
 use bigdecimal::num_bigint::BigInt;
+use crate::bytecode::archive::Archive;
 use crate::bytecode::bytecode::Bytecode;
+use crate::bytecode::error::{DecodeError, DecodeErrorKind, DecodeResult};
+use crate::bytecode::module::{Module, FORMAT_VERSION, VARINT_FORMAT_VERSION};
 
-pub struct BytecodeReader {
-    pub bytes: Vec<u8>,
-    pub position: usize,
+enum Source<'a> {
+    Memory(Vec<u8>),
+    Stream(Box<dyn BufRead + 'a>),
 }
 
-impl BytecodeReader {
-    
-    pub fn new(bytecode: Vec<u8>) -> Self { BytecodeReader { bytes: bytecode, position: 0 } }
-    
-    pub fn has_next(&self) -> bool { self.position < self.bytes.len() }
-    
-    pub fn read_u8(&mut self) -> Option<u8> {
-        if !self.has_next() {
-            None
-        } else {
-            let value = self.bytes[self.position];
-            self.position += 1;
-            Some(value)
-        }
+pub struct BytecodeReader<'a> {
+    source: Source<'a>,
+    pub position: usize, // 已读取的字节数
+    pub version: u16, // 输入的模块格式版本，决定整数的编码方式
+}
+
+impl<'a> BytecodeReader<'a> {
+
+    pub fn new(bytecode: Vec<u8>) -> Self { Self::with_version(bytecode, FORMAT_VERSION) }
+
+    pub fn with_version(bytecode: Vec<u8>, version: u16) -> Self {
+        BytecodeReader { source: Source::Memory(bytecode), position: 0, version }
+    }
+
+    // 从 io::Read 逐步读取，不需要先把整个输入读进内存
+    pub fn from_reader(reader: impl Read + 'a) -> Self { Self::from_buf_reader(BufReader::new(reader)) }
+
+    pub fn from_buf_reader(reader: impl BufRead + 'a) -> Self {
+        BytecodeReader { source: Source::Stream(Box::new(reader)), position: 0, version: FORMAT_VERSION }
     }
-    
-    pub fn read_i8(&mut self) -> Option<i8> { Some(self.read_u8()? as i8) }
-    
-    pub fn read_u16(&mut self) -> Option<u16> {
-        if !self.has_next() || self.position + 1 >= self.bytes.len() {
-            None
-        } else {
-            let value = u16::from_be_bytes([
-                self.bytes[self.position],
-                self.bytes[self.position + 1],
-            ]);
-            self.position += 2;
-            Some(value)
+
+    pub fn has_next(&mut self) -> bool {
+        match &mut self.source {
+            Source::Memory(bytes) => self.position < bytes.len(),
+            Source::Stream(stream) => stream.fill_buf().is_ok_and(|buffer| !buffer.is_empty()),
         }
     }
-    
-    pub fn read_i16(&mut self) -> Option<i16> { Some(self.read_u16()? as i16) }
-    
-    pub fn read_u32(&mut self) -> Option<u32> {
-        if !self.has_next() || self.position + 3 >= self.bytes.len() {
-            None
-        } else {
-            let value = u32::from_be_bytes([
-                self.bytes[self.position],
-                self.bytes[self.position + 1],
-                self.bytes[self.position + 2],
-                self.bytes[self.position + 3],
-            ]);
-            self.position += 4;
-            Some(value)
+
+    // 剩余的字节数，流式输入时未知
+    fn remaining(&self) -> Option<usize> {
+        match &self.source {
+            Source::Memory(bytes) => Some(bytes.len().saturating_sub(self.position)),
+            Source::Stream(_) => None,
         }
     }
-    
-    pub fn read_i32(&mut self) -> Option<i32> { Some(self.read_u32()? as i32) }
-    
-    pub fn read_u64(&mut self) -> Option<u64> {
-        if !self.has_next() || self.position + 7 >= self.bytes.len() {
-            None
-        } else {
-            let value = u64::from_be_bytes([
-                self.bytes[self.position],
-                self.bytes[self.position + 1],
-                self.bytes[self.position + 2],
-                self.bytes[self.position + 3],
-                self.bytes[self.position + 4],
-                self.bytes[self.position + 5],
-                self.bytes[self.position + 6],
-                self.bytes[self.position + 7],
-            ]);
-            self.position += 8;
-            Some(value)
+
+    fn read_array<const N: usize>(&mut self, expected: &'static str) -> DecodeResult<[u8; N]> {
+        let mut array = [0; N];
+        match &mut self.source {
+            Source::Memory(bytes) => {
+                if bytes.len().saturating_sub(self.position) < N {
+                    return Err(DecodeError::new(self.position, expected, DecodeErrorKind::UnexpectedEnd));
+                }
+                array.copy_from_slice(&bytes[self.position..self.position + N]);
+            }
+            Source::Stream(stream) => {
+                stream.read_exact(&mut array).map_err(|error| Self::io_error(self.position, expected, error))?;
+            }
         }
+        self.position += N;
+        Ok(array)
     }
-    
-    pub fn read_i64(&mut self) -> Option<i64> { Some(self.read_u64()? as i64) }
-    
-    pub fn read_u128(&mut self) -> Option<u128> {
-        if !self.has_next() || self.position + 15 >= self.bytes.len() {
-            None
-        } else {
-            let value = u128::from_be_bytes([
-                self.bytes[self.position],
-                self.bytes[self.position + 1],
-                self.bytes[self.position + 2],
-                self.bytes[self.position + 3],
-                self.bytes[self.position + 4],
-                self.bytes[self.position + 5],
-                self.bytes[self.position + 6],
-                self.bytes[self.position + 7],
-                self.bytes[self.position + 8],
-                self.bytes[self.position + 9],
-                self.bytes[self.position + 10],
-                self.bytes[self.position + 11],
-                self.bytes[self.position + 12],
-                self.bytes[self.position + 13],
-                self.bytes[self.position + 14],
-                self.bytes[self.position + 15],
-            ]);
-            self.position += 16;
-            Some(value)
+
+    fn io_error(offset: usize, expected: &'static str, error: std::io::Error) -> DecodeError {
+        match error.kind() {
+            ErrorKind::UnexpectedEof => DecodeError::new(offset, expected, DecodeErrorKind::UnexpectedEnd),
+            kind => DecodeError::new(offset, expected, DecodeErrorKind::Io(kind)),
         }
     }
-    
-    pub fn read_i128(&mut self) -> Option<i128> { Some(self.read_u128()? as i128) }
-    
-    pub fn read_bool(&mut self) -> Option<bool> {
+
+    pub fn read_u8(&mut self) -> DecodeResult<u8> { Ok(self.read_array::<1>("u8")?[0]) }
+
+    pub fn read_i8(&mut self) -> DecodeResult<i8> { Ok(self.read_u8()? as i8) }
+
+    pub fn read_u16(&mut self) -> DecodeResult<u16> { Ok(u16::from_be_bytes(self.read_array("u16")?)) }
+
+    pub fn read_i16(&mut self) -> DecodeResult<i16> { Ok(self.read_u16()? as i16) }
+
+    pub fn read_u32(&mut self) -> DecodeResult<u32> { Ok(u32::from_be_bytes(self.read_array("u32")?)) }
+
+    pub fn read_i32(&mut self) -> DecodeResult<i32> { Ok(self.read_u32()? as i32) }
+
+    pub fn read_u64(&mut self) -> DecodeResult<u64> { Ok(u64::from_be_bytes(self.read_array("u64")?)) }
+
+    pub fn read_i64(&mut self) -> DecodeResult<i64> { Ok(self.read_u64()? as i64) }
+
+    pub fn read_u128(&mut self) -> DecodeResult<u128> { Ok(u128::from_be_bytes(self.read_array("u128")?)) }
+
+    pub fn read_i128(&mut self) -> DecodeResult<i128> { Ok(self.read_u128()? as i128) }
+
+    pub fn read_bool(&mut self) -> DecodeResult<bool> {
         let value = self.read_u8()?;
-        Some(value != 0)
+        Ok(value != 0)
     }
-    
-    pub fn read_f32(&mut self) -> Option<f32> {
+
+    pub fn read_f32(&mut self) -> DecodeResult<f32> {
         let bits = self.read_u32()?;
-        Some(f32::from_bits(bits))
+        Ok(f32::from_bits(bits))
     }
-    
-    pub fn read_f64(&mut self) -> Option<f64> {
+
+    pub fn read_f64(&mut self) -> DecodeResult<f64> {
         let bits = self.read_u64()?;
-        Some(f64::from_bits(bits))
+        Ok(f64::from_bits(bits))
     }
-    
-    pub fn read_isize(&mut self) -> Option<isize> {
+
+    pub fn read_isize(&mut self) -> DecodeResult<isize> {
         let value = self.read_u64()?;
-        Some(value as isize)
+        Ok(value as isize)
     }
-    
-    pub fn read_usize(&mut self) -> Option<usize> {
-        let value = self.read_u64()?;
-        Some(value as usize)
+
+    pub fn read_usize(&mut self) -> DecodeResult<usize> {
+        let start = self.position;
+        let value = if self.version >= VARINT_FORMAT_VERSION { self.read_varint()? } else { self.read_u64()? };
+        usize::try_from(value).map_err(|_| DecodeError::new(start, "usize", DecodeErrorKind::IntegerOverflow))
     }
-    
-    pub fn read_char(&mut self) -> Option<char> {
-        let length = self.read_usize()?;
-        if length > self.bytes.len() - self.position {
-            return None; // 长度超出边界
+
+    pub fn read_varint(&mut self) -> DecodeResult<u64> {
+        let start = self.position;
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_array::<1>("varint")?[0];
+            let bits = (byte & 0x7F) as u64;
+            if shift == 63 && bits > 1 {
+                break; // 超出 64 位
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
         }
-        let bytes = &self.bytes[self.position..self.position + length];
-        self.position += length;
-        String::from_utf8(bytes.to_vec()).ok().and_then(|s| s.chars().next())
+        Err(DecodeError::new(start, "varint", DecodeErrorKind::IntegerOverflow))
     }
-    
-    pub fn read_string(&mut self) -> Option<String> {
+
+    pub fn read_char(&mut self) -> DecodeResult<char> {
+        let start = self.position;
         let length = self.read_usize()?;
-        if length > self.bytes.len() - self.position {
-            return None; // 长度超出边界
+        let bytes = self.read_sized(length, "char")?;
+        let value = String::from_utf8(bytes).map_err(|_| DecodeError::new(start, "char", DecodeErrorKind::InvalidUtf8))?;
+        let mut chars = value.chars();
+        match (chars.next(), chars.next()) {
+            (Some(char), None) => Ok(char),
+            _ => Err(DecodeError::new(start, "char", DecodeErrorKind::InvalidChar)),
         }
-        let bytes = &self.bytes[self.position..self.position + length];
-        self.position += length;
-        Some(String::from_utf8_lossy(bytes).to_string())
     }
-    
-    pub fn read_big_decimal(&mut self) -> Option<BigDecimal> {
-        Some(BigDecimal::from_str(self.read_string()?.as_str()).ok()?)
+
+    pub fn read_string(&mut self) -> DecodeResult<String> {
+        let start = self.position;
+        let length = self.read_usize()?;
+        let bytes = self.read_sized(length, "string")?;
+        String::from_utf8(bytes).map_err(|_| DecodeError::new(start, "string", DecodeErrorKind::InvalidUtf8))
+    }
+
+    pub fn read_big_decimal(&mut self) -> DecodeResult<BigDecimal> {
+        let digits = self.read_big_int()?;
+        let scale = self.read_i64()?;
+        Ok(BigDecimal::new(digits, scale))
     }
 
-    pub fn read_big_int(&mut self) -> Option<BigInt> {
-        Some(BigInt::from_str(self.read_string()?.as_str()).ok()?)
+    pub fn read_big_int(&mut self) -> DecodeResult<BigInt> {
+        let length = self.read_usize()?;
+        let bytes = self.read_sized(length, "big integer")?;
+        Ok(BigInt::from_signed_bytes_be(&bytes))
     }
-    
-    pub fn read_bytecode(&mut self) -> Option<Bytecode> {
+
+    pub fn read_bytes(&mut self, length: usize) -> DecodeResult<Vec<u8>> {
+        self.read_sized(length, "bytes")
+    }
+
+    fn read_sized(&mut self, length: usize, expected: &'static str) -> DecodeResult<Vec<u8>> {
+        let bytes = match &mut self.source {
+            Source::Memory(bytes) => {
+                if length > bytes.len().saturating_sub(self.position) {
+                    return Err(DecodeError::new(self.position, expected, DecodeErrorKind::UnexpectedEnd)); // 长度超出边界
+                }
+                bytes[self.position..self.position + length].to_vec()
+            }
+            Source::Stream(stream) => {
+                // 长度可能已损坏，按实际读到的字节分配
+                let mut bytes = Vec::new();
+                stream.take(length as u64).read_to_end(&mut bytes)
+                    .map_err(|error| Self::io_error(self.position, expected, error))?;
+                if bytes.len() < length {
+                    return Err(DecodeError::new(self.position, expected, DecodeErrorKind::UnexpectedEnd));
+                }
+                bytes
+            }
+        };
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn read_bytecode(&mut self) -> DecodeResult<Bytecode> {
         Bytecode::read(self)
     }
-    
-    pub fn read_vec<T, F>(&mut self, read_element: F) -> Option<Vec<T>>
+
+    // 读取剩余的整个函数体，并把跳转目标从字节偏移换算为指令下标（与 write_instructions 相反）。
+    // 同时返回每条指令原来的偏移，最后一项是函数体的长度
+    pub fn read_instructions(&mut self) -> DecodeResult<(Vec<Bytecode>, Vec<usize>)> {
+        let start = self.position;
+        let mut instructions = Vec::new();
+        let mut offsets = Vec::new();
+        while self.has_next() {
+            offsets.push(self.position - start);
+            instructions.push(self.read_bytecode()?);
+        }
+        offsets.push(self.position - start);
+        let instructions = instructions.iter().enumerate().map(|(index, instruction)| match instruction.get_jump_target() {
+            Some(target) => match offsets.binary_search(&target) {
+                Ok(target) => Ok(instruction.with_jump_target(target)),
+                Err(_) => Err(DecodeError::new(
+                    start + offsets[index], "instruction", DecodeErrorKind::InvalidJumpTarget(target)
+                )),
+            },
+            None => Ok(instruction.clone()),
+        }).collect::<DecodeResult<_>>()?;
+        Ok((instructions, offsets))
+    }
+
+    pub fn read_module(&mut self) -> DecodeResult<Module> {
+        Module::read(self)
+    }
+
+    pub fn read_archive(&mut self) -> DecodeResult<Archive> {
+        Archive::read(self)
+    }
+
+    pub fn read_vec<T, F>(&mut self, read_element: F) -> DecodeResult<Vec<T>>
     where
-        F: Fn(&mut BytecodeReader) -> Option<T>,
+        F: Fn(&mut BytecodeReader<'a>) -> DecodeResult<T>,
     {
         let length = self.read_usize()?;
-        let mut vec = Vec::with_capacity(length);
+        let mut vec = Vec::with_capacity(length.min(self.remaining().unwrap_or(0))); // 长度可能已损坏，不按它预先分配
         for _ in 0..length {
-            if let Some(element) = read_element(self) {
-                vec.push(element);
-            } else {
-                return None; // 读取元素失败
-            }
+            vec.push(read_element(self)?);
         }
-        Some(vec)
+        Ok(vec)
     }
 }
 
-impl Iterator for BytecodeReader {
+impl Iterator for BytecodeReader<'_> {
     type Item = Bytecode;
-    fn next(&mut self) -> Option<Bytecode> { self.read_bytecode() }
+    // 遇到无法解码的指令时停止，需要错误信息时使用 read_bytecode
+    fn next(&mut self) -> Option<Bytecode> { self.read_bytecode().ok() }
 }
@@ -0,0 +1,248 @@
+use crate::bytecode::bytecode::Bytecode;
+use crate::bytecode::constant::Constant;
+use crate::bytecode::module::{FunctionDefinition, Module};
+use crate::bytecode::reader::BytecodeReader;
+
+// 从 `(T1,T2)R` 形式的签名中数出参数个数
+pub fn count_parameters(signature: &str) -> Option<usize> {
+    let end = signature.strip_prefix('(')?.find(')')?;
+    let parameters = &signature[1..end + 1];
+    if parameters.is_empty() {
+        Some(0)
+    } else {
+        Some(parameters.split(',').count())
+    }
+}
+
+fn expect_string(module: &Module, index: usize) -> Result<&str, String> {
+    match module.get_constant(index) {
+        Some(Constant::String(value)) => Ok(value),
+        Some(_) => Err(format!("constant #{} is not a string", index)),
+        None => Err(format!("constant pool index #{} out of range", index)),
+    }
+}
+
+fn expect_class(module: &Module, index: usize) -> Result<(), String> {
+    match module.get_constant(index) {
+        Some(Constant::Class(name)) => expect_string(module, *name).map(|_| ()),
+        Some(_) => Err(format!("constant #{} is not a class reference", index)),
+        None => Err(format!("constant pool index #{} out of range", index)),
+    }
+}
+
+// 返回被调用函数的参数个数
+fn expect_function(module: &Module, index: usize) -> Result<usize, String> {
+    match module.get_constant(index) {
+        Some(Constant::Function { name, signature }) => {
+            expect_string(module, *name)?;
+            let signature = expect_string(module, *signature)?;
+            count_parameters(signature).ok_or_else(|| format!("invalid signature '{}' in constant #{}", signature, index))
+        }
+        Some(_) => Err(format!("constant #{} is not a function reference", index)),
+        None => Err(format!("constant pool index #{} out of range", index)),
+    }
+}
+
+fn expect_value(module: &Module, index: usize) -> Result<(), String> {
+    match module.get_constant(index) {
+        Some(Constant::Class(_) | Constant::Function { .. }) => Err(format!("constant #{} cannot be loaded as a value", index)),
+        Some(_) => Ok(()),
+        None => Err(format!("constant pool index #{} out of range", index)),
+    }
+}
+
+// 指令的栈效果：(需要弹出的个数, 压入的个数)
+fn get_stack_effect(module: &Module, instruction: &Bytecode) -> Result<(usize, usize), String> {
+    Ok(match instruction {
+        Bytecode::Metadata { .. } | Bytecode::Nop | Bytecode::Constant(_) => (0, 0),
+        Bytecode::LoadConst(index) => {
+            expect_value(module, *index)?;
+            (0, 1)
+        }
+        Bytecode::GetObject(index) | Bytecode::NewObject(index) => {
+            expect_class(module, *index)?;
+            (0, 1)
+        }
+        Bytecode::Load => (1, 1),
+        Bytecode::Store(_) => (1, 0),
+        Bytecode::LoadLocal(_) => (0, 1),
+        Bytecode::Pop => (1, 0),
+        Bytecode::Dup => (1, 2),
+        Bytecode::Swap => (2, 2),
+        Bytecode::Invoke(index) => (expect_function(module, *index)?, 1),
+        Bytecode::InvokeVirtual(index, arguments)
+        | Bytecode::InvokeSpecial(index, arguments)
+        | Bytecode::InvokeInterface(index, arguments) => {
+            let parameters = expect_function(module, *index)?;
+            if parameters != *arguments {
+                return Err(format!(
+                    "Argument count {} does not match the signature of constant #{} ({} parameters)", arguments, index, parameters
+                ));
+            }
+            (arguments + 1, 1) // 加上接收者
+        }
+        Bytecode::Return => (0, 0), // 空栈返回 null
+        Bytecode::Jump(_) => (0, 0),
+        Bytecode::JumpIfTrue(_) | Bytecode::JumpIfFalse(_) => (1, 0),
+        Bytecode::GetField(index) => {
+            expect_string(module, *index)?;
+            (1, 1)
+        }
+        Bytecode::SetField(index) => {
+            expect_string(module, *index)?;
+            (2, 0)
+        }
+        Bytecode::CheckCast(index) => {
+            expect_class(module, *index)?;
+            (1, 1)
+        }
+        Bytecode::InstanceOf(index) => {
+            expect_class(module, *index)?;
+            (1, 1)
+        }
+        Bytecode::Throw => (1, 0),
+        Bytecode::Neg | Bytecode::Pos | Bytecode::Not => (1, 1),
+        Bytecode::Add
+        | Bytecode::Sub
+        | Bytecode::Mul
+        | Bytecode::Div
+        | Bytecode::Mod
+        | Bytecode::Pow
+        | Bytecode::And
+        | Bytecode::Or
+        | Bytecode::Equal
+        | Bytecode::NotEqual
+        | Bytecode::IdentityEqual
+        | Bytecode::IdentityNotEqual
+        | Bytecode::Less
+        | Bytecode::LessEqual
+        | Bytecode::Greater
+        | Bytecode::GreaterEqual => (2, 1),
+    })
+}
+
+pub fn verify_function(module: &Module, function: &FunctionDefinition) -> Result<(), String> {
+    let name = expect_string(module, function.name)?;
+    let at = |offset: usize| move |error: String| format!("{} in function '{}' at offset {:04}", error, name, offset);
+    let mut reader = BytecodeReader::new(function.code.clone());
+    let mut instructions = Vec::new();
+    let mut offsets = Vec::new();
+    while reader.has_next() {
+        let offset = reader.position;
+        let instruction = reader.read_bytecode()
+            .map_err(|error| at(offset)(format!("Invalid instruction ({})", error)))?;
+        offsets.push(offset);
+        instructions.push(instruction);
+    }
+    let locals = function.locals.max(function.parameters);
+    // 跳到字节码末尾和执行到末尾一样隐式返回 null，与汇编器和 read_instructions 一致
+    let get_target = |index: usize, target: usize| {
+        if target == function.code.len() {
+            return Ok(instructions.len());
+        }
+        offsets.binary_search(&target).map_err(|_| at(offsets[index])(format!("Jump target {:04} is not an instruction boundary", target)))
+    };
+    // 沿控制流传播每条指令执行前的栈高度，汇合点的高度必须一致
+    let mut heights: Vec<Option<usize>> = vec![None; instructions.len()];
+    let mut worklist = Vec::new();
+    if !instructions.is_empty() {
+        heights[0] = Some(0);
+        worklist.push(0);
+    }
+    // 进入异常处理代码时操作数栈只有被抛出的值
+    let boundary = |offset: usize| offset == function.code.len() || offsets.binary_search(&offset).is_ok();
+    for handler in &function.handlers {
+        let at = |error: String| format!("{} in exception handler of function '{}'", error, name);
+        if handler.start > handler.end || !boundary(handler.start) || !boundary(handler.end) {
+            return Err(at(format!("Invalid range {:04}..{:04}", handler.start, handler.end)));
+        }
+        let Ok(index) = offsets.binary_search(&handler.handler) else {
+            return Err(at(format!("Handler offset {:04} is not an instruction boundary", handler.handler)));
+        };
+        if let Some(class) = handler.class {
+            expect_class(module, class).map_err(at)?;
+        }
+        match heights[index] {
+            None => {
+                heights[index] = Some(1);
+                worklist.push(index);
+            }
+            Some(1) => {}
+            Some(height) => return Err(at(format!("Inconsistent operand stack height ({} and 1)", height))),
+        }
+    }
+    for variable in &function.variables {
+        let at = |error: String| format!("{} in local variable table of function '{}'", error, name);
+        if variable.start > variable.end || !boundary(variable.start) || !boundary(variable.end) {
+            return Err(at(format!("Invalid range {:04}..{:04}", variable.start, variable.end)));
+        }
+        if variable.slot >= locals {
+            return Err(at(format!("Local variable {} out of range ({} locals)", variable.slot, locals)));
+        }
+        expect_string(module, variable.name).map_err(at)?;
+        expect_string(module, variable.value_type).map_err(at)?;
+    }
+    while let Some(index) = worklist.pop() {
+        let instruction = &instructions[index];
+        let height = heights[index].unwrap();
+        let (pops, pushes) = get_stack_effect(module, instruction).map_err(at(offsets[index]))?;
+        if height < pops {
+            return Err(at(offsets[index])(format!("Operand stack underflow in {}", instruction.get_name())));
+        }
+        if let Bytecode::Store(local) | Bytecode::LoadLocal(local) = instruction && *local >= locals {
+            return Err(at(offsets[index])(format!("Local variable {} out of range ({} locals)", local, locals)));
+        }
+        let height = height - pops + pushes;
+        let successors = match instruction {
+            Bytecode::Return | Bytecode::Throw => vec![],
+            Bytecode::Jump(target) => vec![get_target(index, *target)?],
+            Bytecode::JumpIfTrue(target) | Bytecode::JumpIfFalse(target) => vec![index + 1, get_target(index, *target)?],
+            _ => vec![index + 1],
+        };
+        for successor in successors {
+            if successor >= instructions.len() {
+                continue; // 执行到函数末尾隐式返回 null
+            }
+            match heights[successor] {
+                None => {
+                    heights[successor] = Some(height);
+                    worklist.push(successor);
+                }
+                Some(expected) if expected != height => {
+                    return Err(at(offsets[successor])(format!(
+                        "Inconsistent operand stack height ({} and {})", expected, height
+                    )));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+// 在执行前静态检查模块，拒绝损坏或不可信的字节码
+pub fn verify_module(module: &Module) -> Result<(), String> {
+    for class in &module.classes {
+        let name = expect_string(module, class.name)?;
+        let in_class = |error: String| format!("{} in class '{}'", error, name);
+        for index in class.super_class.iter().chain(&class.interfaces).chain(&class.fields) {
+            expect_string(module, *index).map_err(in_class)?;
+        }
+        for method in &class.methods {
+            if *method >= module.functions.len() {
+                return Err(in_class(format!("method index {} out of range", method)));
+            }
+        }
+    }
+    for native in &module.natives {
+        expect_function(module, *native).map_err(|error| format!("{} in native declarations", error))?;
+        if let Some(Constant::Function { name, .. }) = module.get_constant(*native)
+            && module.functions.iter().any(|function| function.name == *name) {
+            return Err(format!("native function '{}' also has a body", expect_string(module, *name)?));
+        }
+    }
+    for function in &module.functions {
+        verify_function(module, function)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,865 @@
+use std::collections::HashMap;
+use bigdecimal::ToPrimitive;
+use lambda_parser::node::declaration::{ClassDeclaration, Declaration, FunctionDeclaration, MemberModifier, VariableDeclaration};
+use lambda_parser::node::expression::{BinaryExpression, BlockExpression, CallExpression, Expression, Identifier, IfExpression, Literal, UnaryExpression};
+use lambda_parser::node::node::TokenRange;
+use lambda_parser::node::program::Program;
+use lambda_parser::node::statement::{BlockStatement, DeclarationStatement, ExpressionStatement, IfStatement, ReturnStatement, Statement};
+use lambda_parser::node::typing::{NamedType, Type, TypeParameter};
+use lambda_parser::parser::typing::qualified_to_string;
+use lambda_parser::tokenizer::token::{Token, TokenKind};
+use crate::bytecode::builder::{BytecodeBuilder, Label};
+use crate::bytecode::bytecode::Bytecode;
+use crate::bytecode::constant::Constant;
+use crate::bytecode::module::{ClassDefinition, FunctionDefinition, LineNumber, LocalVariable, Module};
+use crate::bytecode::optimizer::{optimize, Code};
+use crate::visitor::{VisitResult, Visitor};
+
+pub const INITIALIZER_NAME: &str = "<init>";
+pub const UNKNOWN_TYPE: &str = "?";
+
+// 函数签名形如 (test.Point,lambda.lang.String)lambda.lang.Int，无法确定的类型记为 ?
+pub fn describe_signature(parameters: &[String], return_type: &str) -> String {
+    format!("({}){}", parameters.join(","), return_type)
+}
+
+pub fn describe_unknown_signature(parameters: usize) -> String {
+    describe_signature(&vec![UNKNOWN_TYPE.to_string(); parameters], UNKNOWN_TYPE)
+}
+
+// 计算每个 token 的行和列（从 1 开始），空白 token 取其后第一个非空白 token 的位置
+pub fn get_source_positions(tokens: &[Token]) -> Vec<(usize, usize)> {
+    let mut positions = Vec::with_capacity(tokens.len());
+    let mut line = 1;
+    let mut column = 1;
+    for token in tokens {
+        positions.push((line, column));
+        match &token.kind {
+            TokenKind::Whitespace(value) => {
+                for character in value.chars() {
+                    if character == '\n' {
+                        line += 1;
+                        column = 1;
+                    } else {
+                        column += 1;
+                    }
+                }
+            }
+            _ => column += token.get_raw().chars().count(),
+        }
+    }
+    let mut next = (line, column);
+    for (token, position) in tokens.iter().zip(positions.iter_mut()).rev() {
+        if token.is_whitespace() {
+            *position = next;
+        } else {
+            next = *position;
+        }
+    }
+    positions
+}
+
+// 源码中的运算符对应的运算指令，运算符函数名由 Bytecode::get_operator_function_name 得出
+pub fn get_operator_bytecode(operator: &str, unary: bool) -> Option<Bytecode> {
+    if unary {
+        return match operator {
+            "+" => Some(Bytecode::Pos),
+            "-" => Some(Bytecode::Neg),
+            "!" => Some(Bytecode::Not),
+            _ => None,
+        };
+    }
+    match operator {
+        "**" => Some(Bytecode::Pow),
+        "*" => Some(Bytecode::Mul),
+        "/" => Some(Bytecode::Div),
+        "%" => Some(Bytecode::Mod),
+        "+" => Some(Bytecode::Add),
+        "-" => Some(Bytecode::Sub),
+        "&" => Some(Bytecode::And),
+        "|" => Some(Bytecode::Or),
+        "==" => Some(Bytecode::Equal),
+        "!=" => Some(Bytecode::NotEqual),
+        "===" => Some(Bytecode::IdentityEqual),
+        "!==" => Some(Bytecode::IdentityNotEqual),
+        ">" => Some(Bytecode::Greater),
+        "<" => Some(Bytecode::Less),
+        ">=" => Some(Bytecode::GreaterEqual),
+        "<=" => Some(Bytecode::LessEqual),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+pub struct ClassInfo {
+    pub super_class: Option<String>,
+    pub interfaces: Vec<String>,
+    pub fields: Vec<String>,
+    pub methods: HashMap<String, FunctionInfo>,
+}
+
+#[derive(Clone)]
+pub struct FunctionInfo {
+    pub parameters: usize,
+    pub signature: String,
+    pub is_operator: bool,
+}
+
+// 正在编译的函数，跳转指令的目标在编译期间为标签
+struct FunctionContext {
+    code: BytecodeBuilder<'static>,
+    scopes: Vec<HashMap<String, usize>>,
+    locals: usize,
+    receiver: Option<String>,
+    position: Option<(usize, usize)>, // 正在编译的源码位置
+    lines: Vec<(usize, usize, usize)>, // (指令下标, 行, 列)
+    variables: Vec<LocalVariable>, // 有效范围为指令下标，作用域结束时确定 end
+    type_parameters: Vec<String>, // 函数的类型参数，局部变量的类型引用它们时记为 ?
+}
+
+pub struct Compiler {
+    pub module: Module,
+    pub imports: HashMap<String, String>, // 简单名 → 全限定名
+    pub functions: HashMap<String, FunctionInfo>, // 全限定名 → 函数信息
+    pub classes: HashMap<String, ClassInfo>,
+    pub positions: Vec<(usize, usize)>, // token 下标 → (行, 列)，为空时不生成行号表
+    pub optimize: bool, // 是否对生成的代码做常量折叠和窥孔优化，默认开启
+    function: Option<FunctionContext>,
+}
+
+impl Compiler {
+    pub fn new(source_file: &str) -> Self {
+        let mut module = Module::new();
+        module.source_file = Some(source_file.to_string());
+        Compiler {
+            module,
+            imports: HashMap::new(),
+            functions: HashMap::new(),
+            classes: HashMap::new(),
+            positions: Vec::new(),
+            optimize: true,
+            function: None,
+        }
+    }
+
+    pub fn compile(program: &Program, source_file: &str) -> Result<Module, String> {
+        Self::compile_with_tokens(program, source_file, &[])
+    }
+
+    // tokens 为解析该程序时的 token 序列，用于生成行号表
+    pub fn compile_with_tokens(program: &Program, source_file: &str, tokens: &[Token]) -> Result<Module, String> {
+        let mut compiler = Compiler::new(source_file);
+        compiler.positions = get_source_positions(tokens);
+        compiler.visit_program(program)?;
+        Ok(compiler.module)
+    }
+
+    pub fn qualify(&self, name: &str) -> String {
+        if self.module.package.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", self.module.package, name)
+        }
+    }
+
+    pub fn resolve(&self, name: &str) -> String {
+        let qualified = self.qualify(name);
+        if self.functions.contains_key(&qualified) || self.classes.contains_key(&qualified) {
+            return qualified;
+        }
+        self.imports.get(name).cloned().unwrap_or(qualified)
+    }
+
+    pub fn resolve_type(&self, value_type: &dyn Type) -> Result<String, String> {
+        let Some(named_type) = (value_type as &dyn Type).downcast::<NamedType>() else {
+            return Err("Expected a named type".to_string());
+        };
+        if named_type.name.0.is_some() {
+            Ok(qualified_to_string(&named_type.name))
+        } else {
+            Ok(self.resolve(named_type.name.1.as_str()))
+        }
+    }
+
+    fn describe_type(&self, value_type: &dyn Type, type_parameters: &[TypeParameter]) -> Result<String, String> {
+        if let Some(named_type) = value_type.downcast::<NamedType>()
+            && named_type.name.0.is_none()
+            && type_parameters.iter().any(|parameter| parameter.name.get_name() == named_type.name.1) {
+            return Ok(UNKNOWN_TYPE.to_string());
+        }
+        self.resolve_type(value_type)
+    }
+
+    pub fn get_function_info(&self, function_declaration: &FunctionDeclaration) -> Result<FunctionInfo, String> {
+        let type_parameters = &function_declaration.type_parameters;
+        let mut parameters = Vec::new();
+        for parameter in &function_declaration.parameters {
+            parameters.push(self.describe_type(parameter.value_type.as_ref(), type_parameters)?);
+        }
+        let return_type = match &function_declaration.return_type {
+            Some(return_type) => self.describe_type(return_type.as_ref(), type_parameters)?,
+            None => UNKNOWN_TYPE.to_string(),
+        };
+        Ok(FunctionInfo {
+            parameters: parameters.len(),
+            signature: describe_signature(&parameters, return_type.as_str()),
+            is_operator: function_declaration.is_operator,
+        })
+    }
+
+    fn get_signature(&self, name: &str, arguments: usize) -> String {
+        match self.functions.get(name) {
+            Some(info) => info.signature.clone(),
+            None => describe_unknown_signature(arguments),
+        }
+    }
+
+    // 用户定义或导入的顶层运算符函数，类中的运算符函数由虚拟机在运行时查找
+    fn get_operator_function(&self, function_name: &str) -> Option<String> {
+        let qualified = self.resolve(function_name);
+        match self.functions.get(&qualified) {
+            Some(info) if info.is_operator => Some(qualified),
+            Some(_) => None,
+            None if self.imports.contains_key(function_name) => Some(qualified),
+            None => None,
+        }
+    }
+
+    fn emit_operator(&mut self, bytecode: Bytecode, arguments: usize) {
+        let function_name = bytecode.get_operator_function_name().unwrap();
+        match self.get_operator_function(function_name) {
+            Some(function_name) => {
+                let signature = self.get_signature(function_name.as_str(), arguments);
+                self.emit_invoke(function_name.as_str(), signature.as_str());
+            }
+            None => {
+                self.emit(bytecode);
+            }
+        }
+    }
+
+    fn context(&mut self) -> &mut FunctionContext {
+        self.function.as_mut().expect("Not inside a function")
+    }
+
+    // 下一条指令的源码位置与上一条不同时记入行号表
+    fn record_position(&mut self) {
+        let context = self.context();
+        if let Some((line, column)) = context.position
+            && context.lines.last().is_none_or(|last| (last.1, last.2) != (line, column)) {
+            context.lines.push((context.code.get_instruction_count(), line, column));
+        }
+    }
+
+    fn emit(&mut self, instruction: Bytecode) -> usize {
+        self.record_position();
+        self.context().code.emit(instruction)
+    }
+
+    fn emit_constant(&mut self, value: Constant) {
+        let index = self.module.add_constant(value);
+        self.emit(Bytecode::LoadConst(index));
+    }
+
+    fn emit_global(&mut self, name: &str) {
+        self.emit_constant(Constant::String(name.to_string()));
+        self.emit(Bytecode::Load);
+    }
+
+    fn emit_null(&mut self) {
+        self.emit_global("null");
+    }
+
+    fn emit_invoke(&mut self, name: &str, signature: &str) {
+        let index = self.module.add_function(name, signature);
+        self.emit(Bytecode::Invoke(index));
+    }
+
+    // 调用接收者的方法，接收者和参数已在栈上
+    fn emit_invoke_method(&mut self, invoke: fn(usize, usize) -> Bytecode, name: &str, info: &FunctionInfo) {
+        let index = self.module.add_function(name, info.signature.as_str());
+        self.emit(invoke(index, info.parameters));
+    }
+
+    // 依次在类本身、父类和接口中查找方法，返回声明该方法的类、方法信息以及是否经由接口找到
+    fn find_method(&self, class: &str, name: &str) -> Option<(String, FunctionInfo, bool)> {
+        let mut current = Some(class);
+        while let Some(class) = current {
+            let info = self.classes.get(class)?;
+            if let Some(method) = info.methods.get(name) {
+                return Some((class.to_string(), method.clone(), false));
+            }
+            current = info.super_class.as_deref();
+        }
+        let mut current = Some(class);
+        while let Some(class) = current {
+            let info = self.classes.get(class)?;
+            for interface in &info.interfaces {
+                if let Some((owner, method, _)) = self.find_method(interface, name) {
+                    return Some((owner, method, true));
+                }
+            }
+            current = info.super_class.as_deref();
+        }
+        None
+    }
+
+    fn new_label(&mut self) -> Label { self.context().code.new_label() }
+
+    // 标签绑定到下一条生成的指令
+    fn bind_label(&mut self, label: Label) -> VisitResult { self.context().code.bind_label(label) }
+
+    // 跳转目标由 end_function 在标签绑定后回填
+    fn emit_jump(&mut self, jump: fn(usize) -> Bytecode, label: Label) -> usize {
+        self.record_position();
+        self.context().code.emit_jump(jump, label)
+    }
+
+    // 之后生成的指令对应到该节点的位置，返回之前的位置以便恢复
+    fn enter_node(&mut self, position: TokenRange) -> Option<(usize, usize)> {
+        let position = self.positions.get(position.start).copied();
+        let context = self.context();
+        let previous = context.position;
+        if position.is_some() {
+            context.position = position;
+        }
+        previous
+    }
+
+    fn leave_node(&mut self, previous: Option<(usize, usize)>) {
+        self.context().position = previous;
+    }
+
+    fn begin_scope(&mut self) { self.context().scopes.push(HashMap::new()); }
+
+    // 作用域中声明的变量到这里失效
+    fn end_scope(&mut self) {
+        let context = self.context();
+        let scope = context.scopes.pop().unwrap();
+        let end = context.code.get_instruction_count();
+        for variable in &mut context.variables {
+            if scope.values().any(|slot| *slot == variable.slot) {
+                variable.end = end;
+            }
+        }
+    }
+
+    // 变量从下一条指令开始有效
+    fn declare_local(&mut self, name: &str, value_type: &str) -> usize {
+        let (name_index, value_type) = (self.module.add_string(name), self.module.add_string(value_type));
+        let context = self.context();
+        let slot = context.locals;
+        context.locals += 1;
+        context.scopes.last_mut().unwrap().insert(name.to_string(), slot);
+        let start = context.code.get_instruction_count();
+        context.variables.push(LocalVariable { name: name_index, value_type, slot, start, end: start });
+        slot
+    }
+
+    fn describe_local_type(&self, value_type: Option<&dyn Type>) -> Result<String, String> {
+        let Some(value_type) = value_type else {
+            return Ok(UNKNOWN_TYPE.to_string());
+        };
+        if let Some(named_type) = value_type.downcast::<NamedType>()
+            && named_type.name.0.is_none()
+            && self.function.as_ref().is_some_and(|context| context.type_parameters.contains(&named_type.name.1)) {
+            return Ok(UNKNOWN_TYPE.to_string());
+        }
+        self.resolve_type(value_type)
+    }
+
+    fn lookup_local(&self, name: &str) -> Option<usize> {
+        let context = self.function.as_ref()?;
+        context.scopes.iter().rev().find_map(|scope| scope.get(name).copied())
+    }
+
+    fn get_receiver(&self) -> Option<&String> {
+        self.function.as_ref().and_then(|context| context.receiver.as_ref())
+    }
+
+    fn has_field(&self, class: &str, name: &str) -> bool {
+        match self.classes.get(class) {
+            Some(info) => {
+                info.fields.iter().any(|field| field == name)
+                    || info.super_class.as_ref().is_some_and(|super_class| self.has_field(super_class, name))
+            }
+            None => false,
+        }
+    }
+
+    fn begin_function(&mut self, receiver: Option<String>) {
+        self.function = Some(FunctionContext {
+            code: BytecodeBuilder::new(),
+            scopes: vec![HashMap::new()],
+            locals: 0,
+            receiver,
+            position: None,
+            lines: Vec::new(),
+            variables: Vec::new(),
+            type_parameters: Vec::new(),
+        });
+        if let Some(receiver) = self.get_receiver().cloned() {
+            self.declare_local("this", receiver.as_str());
+        }
+    }
+
+    fn end_function(&mut self, name: &str, parameters: usize) -> Result<usize, String> {
+        self.emit(Bytecode::Return);
+        while !self.context().scopes.is_empty() {
+            self.end_scope();
+        }
+        let mut context = self.function.take().unwrap();
+        let name = self.module.add_string(name);
+        let instructions = context.code.resolve_labels()?;
+        let mut code = Code { instructions, lines: context.lines, handlers: Vec::new(), variables: context.variables };
+        if self.optimize {
+            code = optimize(&mut self.module, code);
+        }
+        let mut builder = BytecodeBuilder::new();
+        let offsets = builder.write_instructions(&code.instructions);
+        let lines = code.lines.iter()
+            .map(|(index, line, column)| LineNumber { offset: offsets[*index], line: *line, column: *column })
+            .collect();
+        let variables = code.variables.iter()
+            .map(|variable| LocalVariable { start: offsets[variable.start], end: offsets[variable.end], ..*variable })
+            .collect();
+        self.module.functions.push(FunctionDefinition {
+            name,
+            parameters,
+            locals: context.locals,
+            operator: false, // operator fn 由 compile_function 标记
+            code: builder.bytes,
+            lines,
+            handlers: Vec::new(), // 语言还没有 try/catch
+            variables,
+        });
+        Ok(self.module.functions.len() - 1)
+    }
+
+    fn compile_function(&mut self, name: &str, function_declaration: &FunctionDeclaration, receiver: Option<String>) -> Result<usize, String> {
+        let Some(body) = &function_declaration.body else {
+            return Err(format!("Function '{}' has no body", name));
+        };
+        self.begin_function(receiver);
+        let type_parameters = &function_declaration.type_parameters;
+        self.context().type_parameters = type_parameters.iter().map(|parameter| parameter.name.get_name()).collect();
+        for parameter in &function_declaration.parameters {
+            if parameter.default_value.is_some() {
+                return Err(format!("Default parameter values are not supported yet: '{}'", parameter.name.get_name()));
+            }
+            let value_type = self.describe_type(parameter.value_type.as_ref(), type_parameters)?;
+            self.declare_local(parameter.name.get_name().as_str(), value_type.as_str());
+        }
+        let parameters = self.context().locals;
+        self.visit_statement(body)?;
+        let index = self.end_function(name, parameters)?;
+        self.module.functions[index].operator = function_declaration.is_operator;
+        Ok(index)
+    }
+
+    fn compile_arguments(&mut self, call_expression: &CallExpression, expected: Option<usize>, name: &str) -> VisitResult {
+        if let Some(expected) = expected && call_expression.arguments.len() != expected {
+            return Err(format!(
+                "'{}' expects {} arguments, but got {}", name, expected, call_expression.arguments.len()
+            ));
+        }
+        for argument in &call_expression.arguments {
+            if argument.name.is_some() || argument.is_rest {
+                return Err("Named and rest arguments are not supported yet".to_string());
+            }
+            self.visit_expression(&argument.value)?;
+        }
+        Ok(())
+    }
+
+    // 类的初始化函数只接收 this：语法还没有构造函数参数，字段由默认值初始化
+    fn add_initializer(&mut self, class: &str) -> usize {
+        let initializer = format!("{}.{}", class, INITIALIZER_NAME);
+        self.module.add_function(initializer.as_str(), describe_unknown_signature(0).as_str())
+    }
+
+    // 预先登记所有顶层声明，以便前向引用
+    fn declare(&mut self, program: &Program) -> VisitResult {
+        for declaration in &program.declarations {
+            if let Some(function_declaration) = declaration.downcast::<FunctionDeclaration>() {
+                let name = self.qualify(function_declaration.name.get_name().as_str());
+                let info = self.get_function_info(function_declaration)?;
+                self.functions.insert(name, info);
+            } else if let Some(class_declaration) = declaration.downcast::<ClassDeclaration>() {
+                let name = self.qualify(class_declaration.name.get_name().as_str());
+                self.classes.insert(name, ClassInfo::default());
+            }
+        }
+        for declaration in &program.declarations {
+            let Some(class_declaration) = declaration.downcast::<ClassDeclaration>() else {
+                continue;
+            };
+            let mut info = ClassInfo {
+                super_class: match &class_declaration.super_class {
+                    Some(super_class) => Some(self.resolve_type(super_class.as_ref())?),
+                    None => None,
+                },
+                ..ClassInfo::default()
+            };
+            for interface in &class_declaration.interfaces {
+                info.interfaces.push(self.resolve_type(interface.as_ref())?);
+            }
+            for member in &class_declaration.body {
+                if let Some(variable_declaration) = member.downcast::<VariableDeclaration>() {
+                    info.fields.push(variable_declaration.name.get_name());
+                } else if let Some(function_declaration) = member.downcast::<FunctionDeclaration>() {
+                    info.methods.insert(function_declaration.name.get_name(), self.get_function_info(function_declaration)?);
+                }
+            }
+            let name = self.qualify(class_declaration.name.get_name().as_str());
+            self.classes.insert(name, info);
+        }
+        Ok(())
+    }
+}
+
+// 将跳转目标从指令下标换算为字节偏移后输出
+pub fn assemble(instructions: &[Bytecode]) -> Vec<u8> {
+    let mut builder = BytecodeBuilder::new();
+    builder.write_instructions(instructions);
+    builder.bytes
+}
+
+impl Visitor for Compiler {
+    fn visit_program(&mut self, program: &Program) -> VisitResult {
+        self.module.package = program.package_definition.name.clone();
+        for import_definition in &program.import_definitions {
+            self.imports.insert(
+                import_definition.member.clone(),
+                format!("{}.{}", import_definition.package_name, import_definition.member),
+            );
+        }
+        self.declare(program)?;
+        for declaration in &program.declarations {
+            self.visit_top_level_declaration(declaration)?;
+        }
+        Ok(())
+    }
+
+    fn visit_top_level_declaration(&mut self, declaration: &Box<dyn Declaration>) -> VisitResult {
+        if let Some(function_declaration) = declaration.downcast::<FunctionDeclaration>() {
+            self.visit_top_level_function_declaration(function_declaration)
+        } else if let Some(variable_declaration) = declaration.downcast::<VariableDeclaration>() {
+            self.visit_top_level_variable_declaration(variable_declaration)
+        } else if let Some(class_declaration) = declaration.downcast::<ClassDeclaration>() {
+            self.visit_class_declaration(class_declaration)
+        } else {
+            Err("Unsupported top-level declaration".to_string())
+        }
+    }
+
+    fn visit_top_level_function_declaration(&mut self, function_declaration: &FunctionDeclaration) -> VisitResult {
+        let name = self.qualify(function_declaration.name.get_name().as_str());
+        // 本地函数只记录声明，由虚拟机在加载时绑定到宿主注册的实现
+        if function_declaration.member_modifier == Some(MemberModifier::Native) {
+            let info = self.get_function_info(function_declaration)?;
+            let native = self.module.add_function(name.as_str(), info.signature.as_str());
+            self.module.natives.push(native);
+            return Ok(());
+        }
+        self.compile_function(name.as_str(), function_declaration, None)?;
+        Ok(())
+    }
+
+    fn visit_top_level_variable_declaration(&mut self, variable_declaration: &VariableDeclaration) -> VisitResult {
+        if variable_declaration.member_modifier == Some(MemberModifier::Native) {
+            return Ok(());
+        }
+        Err(format!("Top-level variables are not supported yet: '{}'", variable_declaration.name.get_name()))
+    }
+
+    fn visit_class_declaration(&mut self, class_declaration: &ClassDeclaration) -> VisitResult {
+        let name = self.qualify(class_declaration.name.get_name().as_str());
+        let super_class = self.classes.get(&name).and_then(|info| info.super_class.clone());
+        let mut interfaces = Vec::new();
+        for interface in &class_declaration.interfaces {
+            let interface = self.resolve_type(interface.as_ref())?;
+            interfaces.push(self.module.add_string(interface.as_str()));
+        }
+        let mut fields = Vec::new();
+        let mut methods = Vec::new();
+        for member in &class_declaration.body {
+            if let Some(variable_declaration) = member.downcast::<VariableDeclaration>() {
+                fields.push(self.module.add_string(variable_declaration.name.get_name().as_str()));
+            } else if let Some(function_declaration) = member.downcast::<FunctionDeclaration>() {
+                if function_declaration.body.is_none() {
+                    continue;
+                }
+                let method = format!("{}.{}", name, function_declaration.name.get_name());
+                methods.push(self.compile_function(method.as_str(), function_declaration, Some(name.clone()))?);
+            }
+        }
+        // 初始化函数：先调用父类初始化，再为字段赋默认值
+        self.begin_function(Some(name.clone()));
+        if let Some(super_class) = &super_class {
+            self.emit(Bytecode::LoadLocal(0));
+            let index = self.add_initializer(super_class);
+            self.emit(Bytecode::InvokeSpecial(index, 0));
+            self.emit(Bytecode::Pop);
+        }
+        for member in &class_declaration.body {
+            let Some(variable_declaration) = member.downcast::<VariableDeclaration>() else {
+                continue;
+            };
+            if let Some(default_value) = &variable_declaration.default_value {
+                self.emit(Bytecode::LoadLocal(0));
+                self.visit_expression(default_value)?;
+                let field = self.module.add_string(variable_declaration.name.get_name().as_str());
+                self.emit(Bytecode::SetField(field));
+            }
+        }
+        methods.push(self.end_function(format!("{}.{}", name, INITIALIZER_NAME).as_str(), 1)?);
+        let class_name = self.module.add_string(name.as_str());
+        let super_class = super_class.map(|super_class| self.module.add_string(super_class.as_str()));
+        self.module.classes.push(ClassDefinition { name: class_name, super_class, interfaces, fields, methods });
+        Ok(())
+    }
+
+    fn visit_statement(&mut self, statement: &Box<dyn Statement>) -> VisitResult {
+        let previous = self.enter_node(statement.get_position());
+        let result = if let Some(if_statement) = statement.downcast::<IfStatement>() {
+            self.visit_if_statement(if_statement)
+        } else if let Some(return_statement) = statement.downcast::<ReturnStatement>() {
+            self.visit_return_statement(return_statement)
+        } else if let Some(block_statement) = statement.downcast::<BlockStatement>() {
+            self.visit_block_statement(block_statement)
+        } else if let Some(expression_statement) = statement.downcast::<ExpressionStatement>() {
+            self.visit_expression_statement(expression_statement)
+        } else if let Some(declaration_statement) = statement.downcast::<DeclarationStatement>() {
+            self.visit_declaration_statement(declaration_statement)
+        } else {
+            Err("Unsupported statement".to_string())
+        };
+        self.leave_node(previous);
+        result
+    }
+
+    fn visit_if_statement(&mut self, if_statement: &IfStatement) -> VisitResult {
+        self.visit_expression(&if_statement.test)?;
+        let alternate_label = self.new_label();
+        self.emit_jump(Bytecode::JumpIfFalse, alternate_label);
+        self.visit_statement(&if_statement.consequent)?;
+        if let Some(alternate) = &if_statement.alternate {
+            let end_label = self.new_label();
+            self.emit_jump(Bytecode::Jump, end_label);
+            self.bind_label(alternate_label)?;
+            self.visit_statement(alternate)?;
+            self.bind_label(end_label)?;
+        } else {
+            self.bind_label(alternate_label)?;
+        }
+        Ok(())
+    }
+
+    fn visit_return_statement(&mut self, return_statement: &ReturnStatement) -> VisitResult {
+        match &return_statement.expression {
+            Some(expression) => self.visit_expression(expression)?,
+            None => self.emit_null(),
+        }
+        self.emit(Bytecode::Return);
+        Ok(())
+    }
+
+    fn visit_block_statement(&mut self, block_statement: &BlockStatement) -> VisitResult {
+        self.begin_scope();
+        for statement in &block_statement.statements {
+            self.visit_statement(statement)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_expression_statement(&mut self, expression_statement: &ExpressionStatement) -> VisitResult {
+        self.visit_expression(&expression_statement.expression)?;
+        self.emit(Bytecode::Pop);
+        Ok(())
+    }
+
+    fn visit_declaration_statement(&mut self, declaration_statement: &DeclarationStatement) -> VisitResult {
+        let Some(variable_declaration) = declaration_statement.declaration.downcast::<VariableDeclaration>() else {
+            return Err("Expected a variable declaration".to_string());
+        };
+        if variable_declaration.getter.is_some() || variable_declaration.setter.is_some() || variable_declaration.delegate.is_some() {
+            return Err("Local variables with getters, setters or delegates are not supported yet".to_string());
+        }
+        match &variable_declaration.default_value {
+            Some(default_value) => self.visit_expression(default_value)?,
+            None => self.emit_null(),
+        }
+        let value_type = self.describe_local_type(variable_declaration.value_type.as_deref())?;
+        let slot = self.declare_local(variable_declaration.name.get_name().as_str(), value_type.as_str());
+        self.emit(Bytecode::Store(slot));
+        self.context().variables.last_mut().unwrap().start += 1; // 赋值之后才有效
+        Ok(())
+    }
+
+    fn visit_expression(&mut self, expression: &Box<dyn Expression>) -> VisitResult {
+        let previous = self.enter_node(expression.get_position());
+        let result = if let Some(literal) = expression.downcast::<Literal>() {
+            self.visit_literal(literal)
+        } else if let Some(identifier) = expression.downcast::<Identifier>() {
+            self.visit_identifier(identifier)
+        } else if let Some(binary_expression) = expression.downcast::<BinaryExpression>() {
+            self.visit_binary_expression(binary_expression)
+        } else if let Some(unary_expression) = expression.downcast::<UnaryExpression>() {
+            self.visit_unary_expression(unary_expression)
+        } else if let Some(call_expression) = expression.downcast::<CallExpression>() {
+            self.visit_call_expression(call_expression)
+        } else if let Some(if_expression) = expression.downcast::<IfExpression>() {
+            self.visit_if_expression(if_expression)
+        } else if let Some(block_expression) = expression.downcast::<BlockExpression>() {
+            self.visit_block_expression(block_expression)
+        } else {
+            Err("Unsupported expression".to_string())
+        };
+        self.leave_node(previous);
+        result
+    }
+
+    fn visit_identifier(&mut self, identifier: &Identifier) -> VisitResult {
+        let name = identifier.get_name();
+        if let Some(slot) = self.lookup_local(name.as_str()) {
+            self.emit(Bytecode::LoadLocal(slot));
+            return Ok(());
+        }
+        if let Some(receiver) = self.get_receiver() && self.has_field(receiver, name.as_str()) {
+            let field = self.module.add_string(name.as_str());
+            self.emit(Bytecode::LoadLocal(0));
+            self.emit(Bytecode::GetField(field));
+            return Ok(());
+        }
+        match name.as_str() {
+            "true" | "false" | "null" => self.emit_global(name.as_str()),
+            _ => {
+                let global = self.resolve(name.as_str());
+                self.emit_global(global.as_str());
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_literal(&mut self, literal: &Literal) -> VisitResult {
+        let constant = if literal.is_string() {
+            Constant::String(literal.get_string())
+        } else if literal.is_character() {
+            Constant::Char(literal.get_character())
+        } else if literal.is_integer() {
+            let value = literal.get_integer();
+            match value.to_i64() {
+                Some(value) => Constant::Int(value),
+                None => Constant::BigInt(value),
+            }
+        } else {
+            // 超出 f64 精度的小数保留为 BigDecimal
+            let value = literal.get_float();
+            match value.to_f64() {
+                Some(float) if value.digits() <= 15 => Constant::Float(float),
+                _ => Constant::BigDecimal(value),
+            }
+        };
+        self.emit_constant(constant);
+        Ok(())
+    }
+
+    fn visit_binary_expression(&mut self, binary_expression: &BinaryExpression) -> VisitResult {
+        let operator = binary_expression.operator.as_str();
+        if operator == "&&" || operator == "||" {
+            // 短路求值：左值已决定结果时保留左值并跳过右侧
+            self.visit_expression(&binary_expression.left)?;
+            self.emit(Bytecode::Dup);
+            let end_label = self.new_label();
+            let jump = if operator == "&&" { Bytecode::JumpIfFalse } else { Bytecode::JumpIfTrue };
+            self.emit_jump(jump, end_label);
+            self.emit(Bytecode::Pop);
+            self.visit_expression(&binary_expression.right)?;
+            self.bind_label(end_label)?;
+            return Ok(());
+        }
+        let Some(bytecode) = get_operator_bytecode(operator, false) else {
+            return Err(format!("Unsupported binary operator: {}", operator));
+        };
+        self.visit_expression(&binary_expression.left)?;
+        self.visit_expression(&binary_expression.right)?;
+        self.emit_operator(bytecode, 2);
+        Ok(())
+    }
+
+    fn visit_unary_expression(&mut self, unary_expression: &UnaryExpression) -> VisitResult {
+        let Some(bytecode) = get_operator_bytecode(unary_expression.operator.as_str(), true) else {
+            return Err(format!("Unsupported unary operator: {}", unary_expression.operator));
+        };
+        self.visit_expression(&unary_expression.expression)?;
+        self.emit_operator(bytecode, 1);
+        Ok(())
+    }
+
+    fn visit_call_expression(&mut self, call_expression: &CallExpression) -> VisitResult {
+        let Some(callee) = call_expression.callee.downcast::<Identifier>() else {
+            return Err("Only named functions can be called".to_string());
+        };
+        let name = callee.get_name();
+        let qualified = self.resolve(name.as_str());
+        if self.classes.contains_key(&qualified) {
+            if !call_expression.arguments.is_empty() {
+                return Err(format!(
+                    "Class '{}' has no constructor parameters, but got {} arguments", qualified, call_expression.arguments.len()
+                ));
+            }
+            let class = self.module.add_class(qualified.as_str());
+            self.emit(Bytecode::NewObject(class));
+            self.emit(Bytecode::Dup);
+            let index = self.add_initializer(qualified.as_str());
+            self.emit(Bytecode::InvokeSpecial(index, 0));
+            self.emit(Bytecode::Pop);
+            return Ok(());
+        }
+        // 类中调用本类、父类或接口的方法，按 this 的实际类型分派
+        if let Some(receiver) = self.get_receiver().cloned()
+            && let Some((owner, info, via_interface)) = self.find_method(receiver.as_str(), name.as_str()) {
+            let method = format!("{}.{}", owner, name);
+            self.emit(Bytecode::LoadLocal(0));
+            self.compile_arguments(call_expression, Some(info.parameters), method.as_str())?;
+            let invoke = if via_interface { Bytecode::InvokeInterface } else { Bytecode::InvokeVirtual };
+            self.emit_invoke_method(invoke, method.as_str(), &info);
+            return Ok(());
+        }
+        let parameters = self.functions.get(&qualified).map(|info| info.parameters);
+        self.compile_arguments(call_expression, parameters, qualified.as_str())?;
+        let signature = self.get_signature(qualified.as_str(), call_expression.arguments.len());
+        self.emit_invoke(qualified.as_str(), signature.as_str());
+        Ok(())
+    }
+
+    fn visit_if_expression(&mut self, if_expression: &IfExpression) -> VisitResult {
+        self.visit_expression(&if_expression.test)?;
+        let (alternate_label, end_label) = (self.new_label(), self.new_label());
+        self.emit_jump(Bytecode::JumpIfFalse, alternate_label);
+        self.visit_expression(&if_expression.consequent)?;
+        self.emit_jump(Bytecode::Jump, end_label);
+        self.bind_label(alternate_label)?;
+        match &if_expression.alternate {
+            Some(alternate) => self.visit_expression(alternate)?,
+            None => self.emit_null(),
+        }
+        self.bind_label(end_label)?;
+        Ok(())
+    }
+
+    fn visit_block_expression(&mut self, block_expression: &BlockExpression) -> VisitResult {
+        self.begin_scope();
+        for statement in &block_expression.statements {
+            self.visit_statement(statement)?;
+        }
+        match &block_expression.return_expression {
+            Some(expression) => self.visit_expression(expression)?,
+            None => self.emit_null(),
+        }
+        self.end_scope();
+        Ok(())
+    }
+}
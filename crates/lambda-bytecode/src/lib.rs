@@ -1,2 +1,1018 @@
 pub mod visitor;
 pub mod bytecode;
+pub mod compiler;
+
+#[cfg(test)]
+mod test {
+    use bigdecimal::BigDecimal;
+    use bigdecimal::num_bigint::BigInt;
+    use lambda_parser::parser::api::Parser;
+    use lambda_parser::tokenizer::tokenizer::{SrcInfo, Tokenizer};
+    use crate::bytecode::archive::{Archive, ARCHIVE_MAGIC};
+    use crate::bytecode::assembler::{assemble_module, Assembler};
+    use crate::bytecode::builder::BytecodeBuilder;
+    use crate::bytecode::bytecode::{Bytecode, OPERATOR_FUNCTIONS};
+    use crate::bytecode::error::{DecodeError, DecodeErrorKind};
+    use crate::bytecode::disassembler::{disassemble, disassemble_module};
+    use crate::bytecode::constant::Constant;
+    use crate::bytecode::optimizer::optimize_function;
+    use crate::bytecode::module::{FunctionDefinition, LineNumber, Module, FORMAT_VERSION, MIN_FORMAT_VERSION, OPERATOR_FORMAT_VERSION, VARINT_FORMAT_VERSION};
+    use crate::bytecode::checksum::crc32;
+    use crate::bytecode::reader::BytecodeReader;
+    use crate::bytecode::verifier::verify_module;
+    use crate::compiler::{assemble, Compiler};
+
+    #[test]
+    fn it_works() {
+        let src = r#"
+        package test
+
+        fn choose(flag: Boolean, a: String, b: String) -> String = if (flag) a else b
+
+        class Point {
+            var x: String = "0"
+            fn getX() -> String = x
+            fn describe() -> String = getX()
+        }
+
+        fn origin() -> String {
+            val point = Point()
+            return choose(true && false, "0", "1")
+        }
+        "#;
+        let src_info = SrcInfo {
+            filename: "test.ld".to_string(),
+        };
+        let program = Parser::new(Tokenizer::new(src, src_info)).parse_program().unwrap();
+        let module = Compiler::compile(&program, "test.ld").unwrap();
+        assert_eq!(module.package, "test");
+        verify_module(&module).unwrap();
+        assert_eq!(module.classes.len(), 1);
+        let names: Vec<&str> = module.functions.iter().map(|function| module.get_string(function.name).unwrap()).collect();
+        assert_eq!(names, vec!["test.choose", "test.Point.getX", "test.Point.describe", "test.Point.<init>", "test.origin"]);
+        let choose: Vec<Bytecode> = BytecodeReader::new(module.functions[0].code.clone()).collect();
+        assert!(matches!(choose.as_slice(), [
+            Bytecode::LoadLocal(0),
+            Bytecode::JumpIfFalse(8),
+            Bytecode::LoadLocal(1),
+            Bytecode::Jump(10),
+            Bytecode::LoadLocal(2),
+            Bytecode::Return,
+        ]));
+    }
+
+    #[test]
+    fn module_format() {
+        let src = r#"
+        package test
+
+        class Point {
+            var x: String = "0"
+        }
+
+        fn origin() -> Point = Point()
+
+        fn sign(flag: Boolean) -> String {
+            if (flag) {
+                return "+"
+            } else {
+                origin()
+            }
+            return "-"
+        }
+        "#;
+        let src_info = SrcInfo {
+            filename: "test.ld".to_string(),
+        };
+        let program = Parser::new(Tokenizer::new(src, src_info)).parse_program().unwrap();
+        let module = Compiler::compile(&program, "test.ld").unwrap();
+        verify_module(&module).unwrap();
+        let mut builder = BytecodeBuilder::new();
+        builder.write_module(&module).unwrap();
+        let bytes = builder.bytes;
+        assert_eq!(&bytes[..4], b"LMBD");
+        assert_eq!(BytecodeReader::new(bytes.clone()).read_module(), Ok(module.clone()));
+
+        let mut foreign = bytes.clone();
+        foreign[0] = b'X';
+        assert_eq!(BytecodeReader::new(foreign).read_module().unwrap_err().kind, DecodeErrorKind::InvalidMagic);
+        let mut stale = bytes.clone();
+        stale[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+        let error = BytecodeReader::new(stale).read_module().unwrap_err();
+        assert_eq!((error.offset, error.kind), (4, DecodeErrorKind::UnsupportedVersion(FORMAT_VERSION + 1)));
+        let truncated = bytes[..bytes.len() - 1].to_vec();
+        let error = BytecodeReader::new(truncated).read_module().unwrap_err();
+        assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd);
+        assert!(error.to_string().contains("unexpected end of input"));
+
+        // 旧版本使用定长整数，读取时函数体转换为当前版本
+        let mut legacy = BytecodeBuilder::with_version(MIN_FORMAT_VERSION);
+        legacy.write_module(&module).unwrap();
+        assert!(legacy.bytes.len() > bytes.len());
+        assert_eq!(BytecodeReader::new(legacy.bytes).read_module(), Ok(module.clone()));
+
+        // 无法转换到旧版本的函数体在输出前报错
+        let mut broken = module;
+        broken.functions[0].code = vec![0xFF];
+        let mut legacy = BytecodeBuilder::with_version(MIN_FORMAT_VERSION);
+        assert_eq!(legacy.write_module(&broken).unwrap_err().kind, DecodeErrorKind::UnknownOpcode(0xFF));
+        assert!(legacy.bytes.is_empty());
+    }
+
+    #[test]
+    fn constructors() {
+        let src = r#"
+        package test
+
+        class Shape {
+            var name: String = "shape"
+        }
+
+        class Circle : Shape {
+            var radius: Int = 1
+        }
+
+        fn make() -> Circle = Circle()
+        "#;
+        let compile = |src: &str| {
+            let src_info = SrcInfo {
+                filename: "test.ld".to_string(),
+            };
+            Compiler::compile(&Parser::new(Tokenizer::new(src, src_info)).parse_program().unwrap(), "test.ld")
+        };
+        let module = compile(src).unwrap();
+        verify_module(&module).unwrap();
+        let make = module.functions.iter().find(|function| module.get_string(function.name) == Some("test.make")).unwrap();
+        let code: Vec<Bytecode> = BytecodeReader::new(make.code.clone()).collect();
+        let [Bytecode::NewObject(_), Bytecode::Dup, Bytecode::InvokeSpecial(initializer, 0), ..] = code.as_slice() else {
+            panic!("unexpected code: {:?}", code)
+        };
+        let Some(Constant::Function { name, signature }) = module.get_constant(*initializer) else { panic!("unexpected initializer reference") };
+        assert_eq!((module.get_string(*name), module.get_string(*signature)), (Some("test.Circle.<init>"), Some("()?")));
+
+        // 语法没有构造函数参数，带参数的构造调用在编译时报错
+        let error = compile(&src.replace("Circle()", "Circle(2)")).unwrap_err();
+        assert_eq!(error, "Class 'test.Circle' has no constructor parameters, but got 1 arguments");
+    }
+
+    #[test]
+    fn varint() {
+        let values = [0, 1, 127, 128, 300, 16383, 16384, u32::MAX as usize, usize::MAX];
+        let mut builder = BytecodeBuilder::new();
+        for value in values {
+            builder.write_usize(value);
+        }
+        assert_eq!(&builder.bytes[..6], &[0x00, 0x01, 0x7F, 0x80, 0x01, 0xAC]);
+        let mut reader = BytecodeReader::new(builder.bytes);
+        for value in values {
+            assert_eq!(reader.read_usize(), Ok(value));
+        }
+        assert!(!reader.has_next());
+        assert_eq!(BytecodeReader::new(vec![0xFF; 11]).read_usize().unwrap_err().kind, DecodeErrorKind::IntegerOverflow);
+        assert_eq!(BytecodeReader::new(vec![0x80]).read_usize().unwrap_err().kind, DecodeErrorKind::UnexpectedEnd);
+    }
+
+    #[test]
+    fn decode_errors() {
+        let error = BytecodeReader::new(vec![0x0E, 0xFF]).read_bytecode().unwrap_err();
+        assert_eq!(error, DecodeError::new(2, "varint", DecodeErrorKind::UnexpectedEnd));
+        assert!(BytecodeReader::new(vec![0x01, 0xEE]).nth(1).is_none());
+        let mut reader = BytecodeReader::new(vec![0x01, 0xEE]);
+        reader.read_bytecode().unwrap();
+        assert_eq!(reader.read_bytecode().unwrap_err(), DecodeError::new(1, "instruction", DecodeErrorKind::UnknownOpcode(0xEE)));
+        let error = BytecodeReader::new(vec![0x02, 0xC3, 0x28]).read_string().unwrap_err();
+        assert_eq!(error, DecodeError::new(0, "string", DecodeErrorKind::InvalidUtf8));
+        let error = BytecodeReader::new(vec![0x05, b'a']).read_string().unwrap_err();
+        assert_eq!(error, DecodeError::new(1, "string", DecodeErrorKind::UnexpectedEnd));
+        assert_eq!(error.to_string(), "unexpected end of input at offset 0001 while reading string");
+
+        // 段内的错误偏移换算为整个模块中的偏移；使用没有校验和的版本，否则先报校验和错误
+        let mut module = Module::new();
+        module.add_string("x");
+        let mut builder = BytecodeBuilder::with_version(VARINT_FORMAT_VERSION);
+        builder.write_module(&module).unwrap();
+        let mut bytes = builder.bytes;
+        let tag = bytes.windows(3).position(|window| window == [0x06, 0x01, b'x']).unwrap(); // 常量段中的字符串常量
+        bytes[tag] = 0x7F;
+        let error = BytecodeReader::new(bytes).read_module().unwrap_err();
+        assert_eq!(error, DecodeError::new(tag, "constant", DecodeErrorKind::UnknownConstantTag(0x7F)));
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+
+        let mut module = Module::new();
+        module.add_string("x");
+        let mut builder = BytecodeBuilder::new();
+        builder.write_module(&module).unwrap();
+        let bytes = builder.bytes;
+        let tag = bytes.windows(3).position(|window| window == [0x06, 0x01, b'x']).unwrap();
+        let mut corrupted = bytes.clone();
+        corrupted[tag + 2] = b'y';
+        let error = BytecodeReader::new(corrupted).read_module().unwrap_err();
+        assert_eq!(error.kind, DecodeErrorKind::ChecksumMismatch(0x02));
+        assert_eq!(error.offset, tag - 2); // 常量段内容的起始位置：常量个数和 Constant 指令的操作码之前
+        // 截断的模块报告输入提前结束
+        let error = BytecodeReader::new(bytes[..bytes.len() - 1].to_vec()).read_module().unwrap_err();
+        assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd);
+        // 旧版本没有校验和，仍然可以读取
+        let mut legacy = BytecodeBuilder::with_version(VARINT_FORMAT_VERSION);
+        legacy.write_module(&module).unwrap();
+        assert_eq!(legacy.bytes.len() + 4 * 9, bytes.len()); // 每段 4 字节
+        assert_eq!(BytecodeReader::new(legacy.bytes).read_module(), Ok(module));
+    }
+
+    #[test]
+    fn disassembler() {
+        let mut module = Module::new();
+        let println = module.add_function("println", "(?)?");
+        let message = module.add_string("hello");
+        let code = assemble(&[
+            Bytecode::LoadConst(message),
+            Bytecode::Invoke(println),
+            Bytecode::JumpIfFalse(0),
+            Bytecode::Return,
+        ]);
+        let listing = disassemble(&code, Some(&module)).unwrap();
+        assert_eq!(listing, format!(
+            "0000 LoadConst #{} ; \"hello\"\n0002 Invoke #{} ; \"println\"\n0004 JumpIfFalse 0000\n0006 Return\n",
+            message, println
+        ));
+        assert!(disassemble(&code[..code.len() - 2], None).unwrap_err().contains("0004"));
+
+        let src = r#"
+        package test
+
+        fn greet(name: String) -> String = name
+        "#;
+        let src_info = SrcInfo {
+            filename: "test.ld".to_string(),
+        };
+        let program = Parser::new(Tokenizer::new(src, src_info)).parse_program().unwrap();
+        let listing = disassemble_module(&Compiler::compile(&program, "test.ld").unwrap()).unwrap();
+        assert!(listing.contains("fn test.greet (parameters: 1, locals: 1)"));
+        assert!(listing.contains("0000 LoadLocal 0"));
+    }
+
+    #[test]
+    fn assembler() {
+        let src = r#"
+        package test
+
+        class Point {
+            var x: String = "0"
+            fn getX() -> String = x
+        }
+
+        fn choose(flag: Boolean, a: String, b: String) -> String = if (flag) a else b
+        fn origin() -> Point = Point()
+        "#;
+        let src_info = SrcInfo {
+            filename: "test.ld".to_string(),
+        };
+        let program = Parser::new(Tokenizer::new(src, src_info)).parse_program().unwrap();
+        let module = Compiler::compile(&program, "test.ld").unwrap();
+        let listing = disassemble_module(&module).unwrap();
+        assert_eq!(assemble_module(&listing), Ok(module));
+
+        let mut assembler = Assembler::new(Module::new());
+        let code = assembler.assemble_code(r#"
+            LoadLocal 0
+            JumpIfFalse else ; 跳到 else 分支
+            LoadConst "yes"
+            Return
+        else:
+            LoadConst 'n'
+            Invoke fn "println" "(?)?"
+            Return
+        "#).unwrap();
+        let instructions: Vec<Bytecode> = BytecodeReader::new(code).collect();
+        let module = assembler.module;
+        assert!(matches!(instructions.as_slice(), [
+            Bytecode::LoadLocal(0),
+            Bytecode::JumpIfFalse(7),
+            Bytecode::LoadConst(_),
+            Bytecode::Return,
+            Bytecode::LoadConst(_),
+            Bytecode::Invoke(_),
+            Bytecode::Return,
+        ]));
+        assert_eq!(module.get_string(0), Some("yes"));
+        assert!(assemble_module("fn f ()\n    Jump missing").unwrap_err().contains("line 2"));
+    }
+
+    #[test]
+    fn verifier() {
+        let verify_code = |mut module: Module, code: Vec<u8>| {
+            let name = module.add_string("f");
+            module.functions.push(FunctionDefinition { name, parameters: 1, locals: 1, code, ..FunctionDefinition::default() });
+            verify_module(&module)
+        };
+        let verify = |code: &str| {
+            let mut assembler = Assembler::new(Module::new());
+            let code = assembler.assemble_code(code).unwrap();
+            verify_code(assembler.module, code)
+        };
+        assert!(verify("LoadLocal 0\nJumpIfFalse end\nLoadConst 1\nReturn\nend:\nLoadConst 2\nReturn").is_ok());
+        // 跳到代码末尾按执行到末尾处理，越过末尾仍然报错
+        assert!(verify("LoadLocal 0\nJumpIfFalse end\nLoadConst 1\nReturn\nend:").is_ok());
+        let mut builder = BytecodeBuilder::new();
+        builder.write_bytecode(Bytecode::Jump(3));
+        assert!(verify_code(Module::new(), builder.bytes).unwrap_err().contains("Jump target 0003"));
+        let mut builder = BytecodeBuilder::new();
+        builder.write_bytecode(Bytecode::Jump(1));
+        assert!(verify_code(Module::new(), builder.bytes).unwrap_err().contains("instruction boundary"));
+        assert!(verify("LoadConst #7").unwrap_err().contains("out of range"));
+        assert!(verify("LoadConst class \"Point\"").unwrap_err().contains("cannot be loaded"));
+        assert!(verify("LoadLocal 1").unwrap_err().contains("Local variable 1"));
+        assert!(verify("Pop").unwrap_err().contains("underflow"));
+        assert!(verify("Invoke fn \"g\" \"(?,?)?\"").unwrap_err().contains("underflow"));
+        let error = verify("LoadLocal 0\nJumpIfTrue end\nLoadConst 1\nend:\nReturn").unwrap_err();
+        assert!(error.contains("Inconsistent operand stack height") && error.contains("function 'f' at offset 0006"));
+    }
+
+    // 每次只读出一个字节，模拟分段到达的输入
+    struct Trickle<'a>(&'a [u8]);
+
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+            let Some((first, rest)) = self.0.split_first() else { return Ok(0) };
+            if buffer.is_empty() {
+                return Ok(0);
+            }
+            buffer[0] = *first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    struct Broken;
+
+    impl std::io::Write for Broken {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> { Err(std::io::ErrorKind::BrokenPipe.into()) }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn streaming() {
+        let src = r#"
+        package test
+
+        fn choose(flag: Boolean, a: String, b: String) -> String = if (flag) a else b
+        "#;
+        let src_info = SrcInfo {
+            filename: "test.ld".to_string(),
+        };
+        let program = Parser::new(Tokenizer::new(src, src_info)).parse_program().unwrap();
+        let mut module = Compiler::compile(&program, "test.ld").unwrap();
+        module.add_string(&"x".repeat(20000)); // 超过输出缓冲区
+        let mut builder = BytecodeBuilder::new();
+        builder.write_module(&module).unwrap();
+
+        let mut bytes = Vec::new();
+        let mut writer = BytecodeBuilder::from_writer(&mut bytes);
+        writer.write_module(&module).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        assert_eq!(bytes, builder.bytes);
+
+        let mut reader = BytecodeReader::from_reader(Trickle(&bytes));
+        assert_eq!(reader.read_module(), Ok(module.clone()));
+        assert!(!reader.has_next());
+        let error = BytecodeReader::from_reader(Trickle(&bytes[..bytes.len() - 3])).read_module().unwrap_err();
+        assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd);
+
+        let mut writer = BytecodeBuilder::from_writer(Broken);
+        writer.write_module(&module).unwrap();
+        assert_eq!(writer.flush().unwrap_err().kind(), std::io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn line_numbers() {
+        let src = "package test\n\nfn sign(flag: Boolean) -> String {\n    if (flag) {\n        return \"+\"\n    }\n    return \"-\"\n}\n";
+        let src_info = SrcInfo {
+            filename: "test.ld".to_string(),
+        };
+        let mut parser = Parser::new(Tokenizer::new(src, src_info));
+        let program = parser.parse_program().unwrap();
+        let module = Compiler::compile_with_tokens(&program, "test.ld", &parser.token_buffer.tokens).unwrap();
+        let sign = &module.functions[0];
+        // LoadLocal 0 / JumpIfFalse / LoadConst "+" / Return / LoadConst "-" / Return
+        assert_eq!(sign.lines, vec![
+            LineNumber { offset: 0, line: 4, column: 9 },
+            LineNumber { offset: 2, line: 4, column: 5 },
+            LineNumber { offset: 4, line: 5, column: 16 },
+            LineNumber { offset: 6, line: 5, column: 9 },
+            LineNumber { offset: 7, line: 7, column: 12 },
+            LineNumber { offset: 9, line: 7, column: 5 },
+        ]);
+        assert_eq!(sign.get_line(3), Some(&LineNumber { offset: 2, line: 4, column: 5 }));
+        assert!(Compiler::compile(&program, "test.ld").unwrap().functions[0].lines.is_empty());
+
+        let mut builder = BytecodeBuilder::new();
+        builder.write_module(&module).unwrap();
+        assert_eq!(BytecodeReader::new(builder.bytes).read_module(), Ok(module.clone()));
+        let mut legacy = BytecodeBuilder::with_version(MIN_FORMAT_VERSION);
+        legacy.write_module(&module).unwrap();
+        assert_eq!(BytecodeReader::new(legacy.bytes).read_module(), Ok(module.clone()));
+
+        let listing = disassemble_module(&module).unwrap();
+        assert!(listing.contains("    line 0004 5:16"));
+        assert_eq!(assemble_module(&listing), Ok(module));
+    }
+
+    #[test]
+    fn operators() {
+        // 解析器的二元运算是右结合的，表达式都加上括号以免依赖这一行为
+        let src = r#"
+        package test
+
+        class Vector {
+            var x: Int = 0
+            operator fn plus(other: Vector) -> Vector = other
+        }
+
+        fn check(w: Int, h: Int) -> Boolean = ((((-w) * h) + 1) >= 0) && !(w === h)
+        fn sum(a: Vector, b: Vector) -> Vector = a + b
+        "#;
+        let src_info = SrcInfo {
+            filename: "test.ld".to_string(),
+        };
+        let program = Parser::new(Tokenizer::new(src, src_info)).parse_program().unwrap();
+        let module = Compiler::compile(&program, "test.ld").unwrap();
+        verify_module(&module).unwrap();
+        let find = |name: &str| {
+            let function = module.functions.iter().find(|function| module.get_string(function.name) == Some(name)).unwrap();
+            BytecodeReader::new(function.code.clone()).collect::<Vec<Bytecode>>()
+        };
+        assert!(matches!(find("test.check").as_slice(), [
+            Bytecode::LoadLocal(0),
+            Bytecode::Neg,
+            Bytecode::LoadLocal(1),
+            Bytecode::Mul,
+            Bytecode::LoadConst(_),
+            Bytecode::Add,
+            Bytecode::LoadConst(_),
+            Bytecode::GreaterEqual,
+            Bytecode::Dup,
+            Bytecode::JumpIfFalse(_),
+            Bytecode::Pop,
+            Bytecode::LoadLocal(0),
+            Bytecode::LoadLocal(1),
+            Bytecode::IdentityEqual,
+            Bytecode::Not,
+            Bytecode::Return,
+        ]));
+        // 类中的运算符函数由虚拟机在运行时查找，只有 operator fn 会被标记
+        assert!(matches!(find("test.sum").as_slice(), [Bytecode::LoadLocal(0), Bytecode::LoadLocal(1), Bytecode::Add, ..]));
+        let operators: Vec<&str> = module.functions.iter()
+            .filter(|function| function.operator)
+            .filter_map(|function| module.get_string(function.name))
+            .collect();
+        assert_eq!(operators, vec!["test.Vector.plus"]);
+        let mut builder = BytecodeBuilder::new();
+        builder.write_module(&module).unwrap();
+        assert_eq!(BytecodeReader::new(builder.bytes).read_module(), Ok(module.clone()));
+        assert_eq!(assemble_module(&disassemble_module(&module).unwrap()), Ok(module.clone()));
+        // 旧版本的函数表无法记录 operator fn
+        let mut legacy = BytecodeBuilder::with_version(OPERATOR_FORMAT_VERSION - 1);
+        assert_eq!(legacy.write_module(&module).unwrap_err().kind, DecodeErrorKind::UnsupportedVersion(OPERATOR_FORMAT_VERSION - 1));
+
+        // 指令和运算符函数名两个方向的查找一致
+        for (bytecode, name) in OPERATOR_FUNCTIONS {
+            assert_eq!(bytecode.get_operator_function_name(), Some(*name));
+            assert_eq!(Bytecode::from_operator_function_name(name).as_ref(), Some(bytecode));
+        }
+        assert_eq!(Bytecode::Jump(0).get_operator_function_name(), None);
+
+        // 顶层的 operator fn 在编译时调用
+        let src = r#"
+        package test
+
+        operator fn times(a: String, b: Int) -> String = a
+        fn repeat(a: String) -> String = a * 3
+        "#;
+        let src_info = SrcInfo {
+            filename: "test.ld".to_string(),
+        };
+        let program = Parser::new(Tokenizer::new(src, src_info)).parse_program().unwrap();
+        let module = Compiler::compile(&program, "test.ld").unwrap();
+        let instructions: Vec<Bytecode> = BytecodeReader::new(module.functions[1].code.clone()).collect();
+        assert!(matches!(instructions.as_slice(), [Bytecode::LoadLocal(0), Bytecode::LoadConst(_), Bytecode::Invoke(_), ..]));
+        assert_eq!(assemble_module(&disassemble_module(&module).unwrap()), Ok(module));
+    }
+
+    #[test]
+    fn exception_tables() {
+        let module = assemble_module(r#"
+            fn f (parameters: 1, locals: 1)
+            start:
+                LoadLocal 0
+                Invoke fn "g" "(?)?"
+                Return
+            end:
+                Pop
+                LoadConst "caught"
+                Return
+                catch start end end class "Error"
+                catch start end end
+        "#).unwrap();
+        let handlers = &module.functions[0].handlers;
+        assert_eq!(handlers.len(), 2);
+        assert_eq!((handlers[0].start, handlers[0].end, handlers[0].handler), (0, 5, 5));
+        assert!(handlers[0].class.is_some() && handlers[1].class.is_none());
+        verify_module(&module).unwrap();
+
+        let listing = disassemble_module(&module).unwrap();
+        assert!(listing.contains("    catch 0000 0005 0005 #"));
+        assert_eq!(assemble_module(&listing), Ok(module.clone()));
+        let mut legacy = BytecodeBuilder::with_version(MIN_FORMAT_VERSION);
+        legacy.write_module(&module).unwrap();
+        assert_eq!(BytecodeReader::new(legacy.bytes).read_module(), Ok(module.clone()));
+
+        // 处理代码入口的栈高度为 1
+        let mut broken = module.clone();
+        broken.functions[0].handlers[1].handler = 0;
+        assert!(verify_module(&broken).unwrap_err().contains("Inconsistent operand stack height"));
+        broken.functions[0].handlers[1].end = 1;
+        assert!(verify_module(&broken).unwrap_err().contains("Invalid range"));
+    }
+
+    #[test]
+    fn invocations() {
+        let src = r#"
+        package test
+
+        open class Animal {
+            open fn speak() -> String = "..."
+            fn greet(name: String) -> String = speak()
+        }
+
+        class Named {
+            fn getName() -> String = "named"
+        }
+
+        class Dog : Animal, Named {
+            fn speak() -> String = "woof"
+            fn run() -> String = greet(getName())
+        }
+
+        fn make() -> Dog = Dog()
+        "#;
+        let src_info = SrcInfo {
+            filename: "test.ld".to_string(),
+        };
+        let program = Parser::new(Tokenizer::new(src, src_info)).parse_program().unwrap();
+        let module = Compiler::compile(&program, "test.ld").unwrap();
+        verify_module(&module).unwrap();
+        let find = |name: &str| {
+            let function = module.functions.iter().find(|function| module.get_string(function.name) == Some(name)).unwrap();
+            BytecodeReader::new(function.code.clone()).collect::<Vec<Bytecode>>()
+        };
+        let describe = |index: usize| match &module.constants[index] {
+            Constant::Function { name, .. } => module.get_string(*name),
+            _ => None,
+        };
+        let run = find("test.Dog.run");
+        let [
+            Bytecode::LoadLocal(0),
+            Bytecode::LoadLocal(0),
+            Bytecode::InvokeInterface(get_name, 0),
+            Bytecode::InvokeVirtual(greet, 1),
+            Bytecode::Return,
+            ..
+        ] = run.as_slice() else { panic!("unexpected code: {:?}", run) };
+        assert_eq!(describe(*get_name), Some("test.Named.getName"));
+        assert_eq!(describe(*greet), Some("test.Animal.greet"));
+        let initializer = find("test.Dog.<init>");
+        assert!(matches!(initializer.as_slice(), [Bytecode::LoadLocal(0), Bytecode::InvokeSpecial(_, 0), Bytecode::Pop, ..]));
+        assert!(matches!(find("test.make").as_slice(), [Bytecode::NewObject(_), Bytecode::Dup, Bytecode::InvokeSpecial(_, 0), ..]));
+
+        let listing = disassemble_module(&module).unwrap();
+        assert!(listing.contains("InvokeVirtual #") && listing.contains(" 1 ; \"test.Animal.greet\""));
+        assert_eq!(assemble_module(&listing), Ok(module.clone()));
+
+        // 参数个数必须与签名一致
+        let error = assemble_module(r#"
+            fn f (parameters: 1, locals: 1)
+                LoadLocal 0
+                InvokeVirtual fn "A.g" "(?)?" 0
+                Return
+        "#).and_then(|module| verify_module(&module)).unwrap_err();
+        assert!(error.contains("Argument count 0 does not match"));
+    }
+
+    #[test]
+    fn optimizer() {
+        let mut module = assemble_module(r#"
+            fn f (parameters: 1, locals: 1)
+            start:
+                Nop
+                LoadConst 2
+                LoadConst 3
+                Mul
+                LoadConst 1
+                Add
+                Neg
+                Dup
+                Pop
+                LoadLocal 0
+                JumpIfFalse first
+                Jump first
+            first:
+                Jump second
+            second:
+                Return
+            dead:
+                LoadConst "dead"
+                Throw
+            handler:
+                Return
+                line start 1:1
+                line dead 9:1
+                catch dead handler handler
+        "#).unwrap();
+        let function = module.functions[0].clone();
+        let function = optimize_function(&mut module, &function).unwrap();
+        let (instructions, offsets) = BytecodeReader::new(function.code.clone()).read_instructions().unwrap();
+        let [
+            Bytecode::LoadConst(value),
+            Bytecode::LoadLocal(0),
+            Bytecode::JumpIfFalse(3),
+            Bytecode::Return,
+        ] = instructions.as_slice() else { panic!("unexpected code: {:?}", instructions) };
+        assert_eq!(module.constants[*value], Constant::Int(-7));
+        assert_eq!(function.lines, vec![LineNumber { offset: 0, line: 1, column: 1 }]);
+        assert!(function.handlers.is_empty());
+        assert_eq!(offsets.last(), Some(&function.code.len()));
+        module.functions[0] = function;
+        verify_module(&module).unwrap();
+
+        // 编译器默认开启优化
+        let src = r#"
+        package test
+
+        fn f() -> Float = ((1 + 2) * 3) / 0.5
+        "#;
+        let src_info = SrcInfo {
+            filename: "test.ld".to_string(),
+        };
+        let program = Parser::new(Tokenizer::new(src, src_info)).parse_program().unwrap();
+        let module = Compiler::compile(&program, "test.ld").unwrap();
+        let code: Vec<Bytecode> = BytecodeReader::new(module.functions[0].code.clone()).collect();
+        let [Bytecode::LoadConst(value), Bytecode::Return] = code.as_slice() else { panic!("unexpected code: {:?}", code) };
+        assert_eq!(module.constants[*value], Constant::Float(18.0));
+    }
+
+    #[test]
+    fn labels() {
+        let mut builder = BytecodeBuilder::new();
+        let (start, end) = (builder.new_label(), builder.new_label());
+        builder.bind_label(start).unwrap();
+        builder.emit(Bytecode::LoadLocal(0));
+        builder.emit_jump(Bytecode::JumpIfFalse, end); // 目标尚未确定
+        builder.emit(Bytecode::Constant(Constant::String("x".repeat(200)))); // 使之后的偏移需要两字节编码
+        builder.emit_jump(Bytecode::Jump, start);
+        builder.bind_label(end).unwrap();
+        builder.emit(Bytecode::Return);
+        let offsets = builder.finish_instructions().unwrap();
+        let (instructions, read_offsets) = BytecodeReader::new(builder.bytes.clone()).read_instructions().unwrap();
+        assert_eq!(offsets, read_offsets);
+        assert!(matches!(instructions.as_slice(), [
+            Bytecode::LoadLocal(0),
+            Bytecode::JumpIfFalse(4),
+            Bytecode::Constant(_),
+            Bytecode::Jump(0),
+            Bytecode::Return,
+        ]));
+        assert!(offsets[4] > 0x80);
+        assert_eq!(builder.get_instruction_count(), 0);
+
+        let mut builder = BytecodeBuilder::new();
+        let label = builder.new_label();
+        builder.emit_jump(Bytecode::Jump, label);
+        assert_eq!(builder.finish_instructions(), Err("Label #0 is not bound".to_string()));
+        builder.bind_label(label).unwrap();
+        assert_eq!(builder.bind_label(label), Err("Label #0 is already bound".to_string()));
+        // 取出函数体后标签失效
+        builder.finish_instructions().unwrap();
+        assert_eq!(builder.bind_label(label), Err("Label #0 is unknown".to_string()));
+        builder.emit_jump(Bytecode::Jump, label);
+        assert_eq!(builder.finish_instructions(), Err("Label #0 is unknown".to_string()));
+    }
+
+    #[test]
+    fn local_variables() {
+        let src = r#"
+        package test
+
+        fn area(w: Int, h: Int) -> Int {
+            if (w > h) {
+                val a: Int = w * h
+                return a
+            }
+            val b = h
+            return b
+        }
+        "#;
+        let src_info = SrcInfo {
+            filename: "test.ld".to_string(),
+        };
+        let program = Parser::new(Tokenizer::new(src, src_info)).parse_program().unwrap();
+        let module = Compiler::compile(&program, "test.ld").unwrap();
+        verify_module(&module).unwrap();
+        let function = &module.functions[0];
+        let describe = |offset: usize| {
+            let mut variables: Vec<String> = function.get_variables(offset).map(|variable| format!(
+                "{}@{}: {}", module.get_string(variable.name).unwrap(), variable.slot, module.get_string(variable.value_type).unwrap()
+            )).collect();
+            variables.sort();
+            variables
+        };
+        let (instructions, offsets) = BytecodeReader::new(function.code.clone()).read_instructions().unwrap();
+        let returns: Vec<usize> = instructions.iter().enumerate()
+            .filter(|(_, instruction)| matches!(instruction, Bytecode::Return))
+            .map(|(index, _)| offsets[index])
+            .collect();
+        let int = module.get_string(function.variables[0].value_type).unwrap();
+        assert_eq!(describe(0), vec![format!("h@1: {}", int), format!("w@0: {}", int)]);
+        assert_eq!(describe(returns[0]), vec![format!("a@2: {}", int), format!("h@1: {}", int), format!("w@0: {}", int)]);
+        assert_eq!(describe(returns[1]), vec!["b@3: ?".to_string(), format!("h@1: {}", int), format!("w@0: {}", int)]);
+
+        let listing = disassemble_module(&module).unwrap();
+        assert!(listing.contains("    local 0000 ") && listing.contains(" ; b: ?"));
+        assert_eq!(assemble_module(&listing), Ok(module.clone()));
+        let mut builder = BytecodeBuilder::new();
+        builder.write_module(&module).unwrap();
+        assert_eq!(BytecodeReader::new(builder.bytes).read_module(), Ok(module.clone()));
+
+        let mut broken = module.clone();
+        broken.functions[0].variables[0].slot = 9;
+        assert!(verify_module(&broken).unwrap_err().contains("in local variable table of function 'test.area'"));
+    }
+
+    #[test]
+    fn archive() {
+        let shapes = assemble_module("package shapes\nfn area (parameters: 0, locals: 0)\n    LoadConst 1\n    Return").unwrap();
+        let colors = assemble_module("package colors\nfn red (parameters: 0, locals: 0)\n    LoadConst \"red\"\n    Return").unwrap();
+        let mut archive = Archive::new();
+        archive.manifest.entry_point = Some("area".to_string());
+        assert_eq!(archive.add_module("shapes.ld", shapes.clone()), 0);
+        assert_eq!(archive.add_module("colors.ld", colors.clone()), 1);
+        assert_eq!(archive.add_module("circle.ld", shapes.clone()), 2);
+        assert_eq!(archive.manifest.packages, vec![("colors".to_string(), vec![1]), ("shapes".to_string(), vec![0, 2])]);
+        assert_eq!(archive.get_package("shapes").len(), 2);
+        assert_eq!(archive.get_module("colors.ld"), Some(&colors));
+
+        let mut builder = BytecodeBuilder::new();
+        builder.write_archive(&archive).unwrap();
+        let bytes = builder.bytes;
+        assert!(bytes.starts_with(&ARCHIVE_MAGIC));
+        assert_eq!(BytecodeReader::new(bytes.clone()).read_archive(), Ok(archive.clone()));
+
+        // 归档本身的编码不随 builder 和 reader 的版本变化
+        let mut legacy = archive.clone();
+        legacy.manifest.format_version = MIN_FORMAT_VERSION;
+        let mut builder = BytecodeBuilder::with_version(MIN_FORMAT_VERSION);
+        builder.write_archive(&legacy).unwrap();
+        assert_eq!(BytecodeReader::new(builder.bytes.clone()).read_archive(), Ok(legacy.clone()));
+        assert_eq!(BytecodeReader::with_version(builder.bytes, MIN_FORMAT_VERSION).read_archive(), Ok(legacy));
+
+        let mut broken = bytes.clone();
+        broken[0] = b'X';
+        assert_eq!(BytecodeReader::new(broken).read_archive().unwrap_err().kind, DecodeErrorKind::InvalidMagic);
+        // 包索引与模块不一致
+        let mut inconsistent = archive.clone();
+        inconsistent.manifest.packages[0].1 = vec![0];
+        let mut builder = BytecodeBuilder::new();
+        builder.write_archive(&inconsistent).unwrap();
+        assert_eq!(BytecodeReader::new(builder.bytes).read_archive().unwrap_err().kind, DecodeErrorKind::InvalidManifest);
+        // 个数相同，但有模块重复而另一个模块没有被索引
+        let mut duplicated = archive.clone();
+        duplicated.manifest.packages[1].1 = vec![0, 0];
+        let mut builder = BytecodeBuilder::new();
+        builder.write_archive(&duplicated).unwrap();
+        assert_eq!(BytecodeReader::new(builder.bytes).read_archive().unwrap_err().kind, DecodeErrorKind::InvalidManifest);
+        // 清单声明的版本与模块不一致：清单紧跟在魔数、归档版本和一字节的长度之后
+        let mut mismatched = bytes.clone();
+        mismatched[7..9].copy_from_slice(&VARINT_FORMAT_VERSION.to_be_bytes());
+        let error = BytecodeReader::new(mismatched).read_archive().unwrap_err();
+        assert_eq!(error.kind, DecodeErrorKind::VersionMismatch(FORMAT_VERSION));
+    }
+
+    // 固定种子的伪随机数（xorshift64），测试失败时可以复现
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize { (self.next() % bound as u64) as usize }
+
+        // 各种数量级的整数，覆盖变长编码的每种长度
+        fn usize(&mut self) -> usize { (self.next() >> self.below(64)) as usize }
+
+        fn char(&mut self) -> char {
+            let bits = [0x7F, 0x7FF, 0xFFFF, 0x10FFFF][self.below(4)];
+            char::from_u32(self.next() as u32 & bits).unwrap_or('\u{FFFD}')
+        }
+
+        fn string(&mut self) -> String { (0..self.below(8)).map(|_| self.char()).collect() }
+
+        fn big_int(&mut self) -> BigInt { BigInt::from(self.next() as i64) * BigInt::from(self.next()) }
+
+        fn constant(&mut self) -> Constant {
+            match self.below(8) {
+                0 => Constant::Int(self.next() as i64),
+                1 => Constant::BigInt(self.big_int()),
+                2 => Constant::Float(f64::from_bits(self.next())),
+                3 => Constant::BigDecimal(BigDecimal::new(self.big_int(), self.next() as i8 as i64)),
+                4 => Constant::Char(self.char()),
+                5 => Constant::String(self.string()),
+                6 => Constant::Class(self.usize()),
+                _ => Constant::Function { name: self.usize(), signature: self.usize() },
+            }
+        }
+
+        // 换成随机的操作数；没有通配分支，新增指令时必须在这里补上
+        fn operands(&mut self, instruction: &Bytecode) -> Bytecode {
+            match instruction {
+                Bytecode::Metadata { .. } => Bytecode::Metadata { source_file: self.string() },
+                Bytecode::Constant(_) => Bytecode::Constant(self.constant()),
+                Bytecode::LoadConst(_) => Bytecode::LoadConst(self.usize()),
+                Bytecode::GetObject(_) => Bytecode::GetObject(self.usize()),
+                Bytecode::NewObject(_) => Bytecode::NewObject(self.usize()),
+                Bytecode::Store(_) => Bytecode::Store(self.usize()),
+                Bytecode::LoadLocal(_) => Bytecode::LoadLocal(self.usize()),
+                Bytecode::Invoke(_) => Bytecode::Invoke(self.usize()),
+                Bytecode::InvokeVirtual(..) => Bytecode::InvokeVirtual(self.usize(), self.usize()),
+                Bytecode::InvokeSpecial(..) => Bytecode::InvokeSpecial(self.usize(), self.usize()),
+                Bytecode::InvokeInterface(..) => Bytecode::InvokeInterface(self.usize(), self.usize()),
+                Bytecode::Jump(_) => Bytecode::Jump(self.usize()),
+                Bytecode::JumpIfTrue(_) => Bytecode::JumpIfTrue(self.usize()),
+                Bytecode::JumpIfFalse(_) => Bytecode::JumpIfFalse(self.usize()),
+                Bytecode::GetField(_) => Bytecode::GetField(self.usize()),
+                Bytecode::SetField(_) => Bytecode::SetField(self.usize()),
+                Bytecode::CheckCast(_) => Bytecode::CheckCast(self.usize()),
+                Bytecode::InstanceOf(_) => Bytecode::InstanceOf(self.usize()),
+                Bytecode::Nop | Bytecode::Load | Bytecode::Pop | Bytecode::Dup | Bytecode::Swap | Bytecode::Return
+                | Bytecode::Throw | Bytecode::Add | Bytecode::Sub | Bytecode::Mul | Bytecode::Div | Bytecode::Mod
+                | Bytecode::Pow | Bytecode::Neg | Bytecode::Pos | Bytecode::Not | Bytecode::And | Bytecode::Or
+                | Bytecode::Equal | Bytecode::NotEqual | Bytecode::IdentityEqual | Bytecode::IdentityNotEqual
+                | Bytecode::Less | Bytecode::LessEqual | Bytecode::Greater | Bytecode::GreaterEqual => instruction.clone(),
+            }
+        }
+    }
+
+    // 每个操作码各取一条指令：操作数位置填入长度 1 的字符串或整数常量都能解码的字节
+    fn get_templates() -> Vec<Bytecode> {
+        (0..=u8::MAX).filter_map(|code| {
+            let mut bytes = vec![code, 0x01];
+            bytes.resize(32, 0x00);
+            match BytecodeReader::new(bytes).read_bytecode() {
+                Ok(instruction) => Some(instruction),
+                Err(error) => {
+                    assert_eq!(error.kind, DecodeErrorKind::UnknownOpcode(code));
+                    None
+                }
+            }
+        }).collect()
+    }
+
+    fn encode(instruction: &Bytecode, version: u16) -> Vec<u8> {
+        let mut builder = BytecodeBuilder::with_version(version);
+        builder.write_bytecode(instruction.clone());
+        builder.bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let templates = get_templates();
+        assert_eq!(templates.len(), 0x2C);
+        let mut random = Random(0x5DEECE66D);
+        for version in [MIN_FORMAT_VERSION, FORMAT_VERSION] {
+            let mut stream = BytecodeBuilder::with_version(version);
+            let mut expected = Vec::new();
+            for template in &templates {
+                for _ in 0..64 {
+                    let instruction = random.operands(template);
+                    let bytes = encode(&instruction, version);
+                    assert_eq!(bytes[0], template.get_code());
+                    let mut reader = BytecodeReader::with_version(bytes.clone(), version);
+                    let decoded = reader.read_bytecode().unwrap();
+                    assert!(!reader.has_next(), "{:?} was not read to the end", instruction);
+                    // NaN 不等于自身，按编码比较，同时保证了 NaN 的位模式不变
+                    assert_eq!(encode(&decoded, version), bytes);
+                    if !matches!(instruction, Bytecode::Constant(Constant::Float(value)) if value.is_nan()) {
+                        assert_eq!(decoded, instruction);
+                    }
+                    stream.write_bytecode(instruction.clone());
+                    expected.push(bytes);
+                }
+            }
+            // 连续的指令流逐条读回，流式读取结果相同
+            let reader = BytecodeReader::with_version(stream.bytes.clone(), version);
+            assert_eq!(reader.map(|instruction| encode(&instruction, version)).collect::<Vec<_>>(), expected);
+            let mut reader = BytecodeReader::from_reader(stream.bytes.as_slice());
+            reader.version = version;
+            assert_eq!(reader.map(|instruction| encode(&instruction, version)).collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn malformed_input() {
+        let mut random = Random(0x2545F4914F6CDD1D);
+        // 任何截断的指令都报告输入提前结束
+        for template in get_templates() {
+            for version in [MIN_FORMAT_VERSION, FORMAT_VERSION] {
+                let bytes = encode(&random.operands(&template), version);
+                for length in 0..bytes.len() {
+                    let error = BytecodeReader::with_version(bytes[..length].to_vec(), version).read_bytecode().unwrap_err();
+                    assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd, "{:?} truncated to {} bytes", template, length);
+                }
+            }
+        }
+        // 随机字节和随机改写的模块都不能引起 panic
+        let mut module = Module::new();
+        let name = module.add_string("f");
+        module.add_constant(random.constant());
+        module.functions.push(FunctionDefinition {
+            name,
+            code: assemble(&[Bytecode::LoadConst(1), Bytecode::JumpIfTrue(0), Bytecode::Return]),
+            lines: vec![LineNumber { offset: 0, line: 1, column: 1 }],
+            ..FunctionDefinition::default()
+        });
+        let mut archive = Archive::new();
+        archive.add_module("f.ld", module.clone());
+        let mut samples = Vec::new();
+        for version in [MIN_FORMAT_VERSION, VARINT_FORMAT_VERSION, FORMAT_VERSION] {
+            let mut builder = BytecodeBuilder::with_version(version);
+            builder.write_module(&module).unwrap();
+            samples.push(builder.bytes);
+        }
+        let mut builder = BytecodeBuilder::new();
+        builder.write_archive(&archive).unwrap();
+        samples.push(builder.bytes);
+        for _ in 0..4000 {
+            let mut bytes = samples[random.below(samples.len())].clone();
+            if random.below(4) == 0 {
+                bytes = (0..random.below(64)).map(|_| random.next() as u8).collect();
+            } else {
+                for _ in 0..=random.below(4) {
+                    let index = random.below(bytes.len());
+                    bytes[index] = random.next() as u8;
+                }
+            }
+            let _ = BytecodeReader::new(bytes.clone()).read_module();
+            let _ = BytecodeReader::new(bytes.clone()).read_archive();
+            let _ = BytecodeReader::new(bytes.clone()).read_instructions();
+            let _ = BytecodeReader::from_reader(bytes.as_slice()).read_module();
+            for version in [MIN_FORMAT_VERSION, FORMAT_VERSION] {
+                let _ = BytecodeReader::with_version(bytes.clone(), version).count();
+            }
+        }
+    }
+
+    #[test]
+    fn natives() {
+        let src = r#"
+        package host
+
+        native fn now() -> lambda.lang.Int
+        native fn log(message: lambda.lang.String)
+        fn tick() -> Int = now()
+        "#;
+        let src_info = SrcInfo {
+            filename: "host.ld".to_string(),
+        };
+        let program = Parser::new(Tokenizer::new(src, src_info)).parse_program().unwrap();
+        let module = Compiler::compile(&program, "host.ld").unwrap();
+        verify_module(&module).unwrap();
+        let natives: Vec<(&str, &str)> = module.natives.iter().map(|index| match module.get_constant(*index) {
+            Some(Constant::Function { name, signature }) => (module.get_string(*name).unwrap(), module.get_string(*signature).unwrap()),
+            constant => panic!("{:?} is not a function reference", constant),
+        }).collect();
+        assert_eq!(natives, vec![("host.now", "()lambda.lang.Int"), ("host.log", "(lambda.lang.String)?")]);
+        assert_eq!(module.functions.len(), 1);
+
+        let listing = disassemble_module(&module).unwrap();
+        assert!(listing.contains("native #"));
+        assert_eq!(assemble_module(&listing), Ok(module.clone()));
+        let mut builder = BytecodeBuilder::new();
+        builder.write_module(&module).unwrap();
+        assert_eq!(BytecodeReader::new(builder.bytes).read_module(), Ok(module.clone()));
+
+        let mut broken = module.clone();
+        broken.natives.push(module.functions[0].name);
+        assert!(verify_module(&broken).unwrap_err().contains("in native declarations"));
+        let mut broken = module.clone();
+        broken.functions[0].name = broken.add_string("host.now");
+        assert_eq!(verify_module(&broken).unwrap_err(), "native function 'host.now' also has a body");
+    }
+}
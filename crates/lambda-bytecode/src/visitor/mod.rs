@@ -28,5 +28,8 @@ pub trait Visitor {
     fn visit_literal(&mut self, literal: &lambda_parser::node::expression::Literal) -> VisitResult { Ok(()) }
     fn visit_binary_expression(&mut self, binary_expression: &lambda_parser::node::expression::BinaryExpression) -> VisitResult { Ok(()) }
     fn visit_unary_expression(&mut self, unary_expression: &lambda_parser::node::expression::UnaryExpression) -> VisitResult { Ok(()) }
+    fn visit_call_expression(&mut self, call_expression: &lambda_parser::node::expression::CallExpression) -> VisitResult { Ok(()) }
+    fn visit_if_expression(&mut self, if_expression: &lambda_parser::node::expression::IfExpression) -> VisitResult { Ok(()) }
+    fn visit_block_expression(&mut self, block_expression: &lambda_parser::node::expression::BlockExpression) -> VisitResult { Ok(()) }
 
 }
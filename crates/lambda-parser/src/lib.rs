@@ -4,6 +4,7 @@ pub mod tokenizer;
 
 #[cfg(test)]
 mod test {
+    use crate::node::declaration::{ClassDeclaration, FunctionDeclaration};
     use crate::parser::api::Parser;
     use crate::tokenizer::tokenizer::{SrcInfo, Tokenizer};
 
@@ -36,4 +37,28 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn operator_function() {
+        let src = r#"
+        package test
+
+        operator fn times(a: String, b: Int) -> String = a
+        fn plain(a: Int) -> Int = a
+
+        class Vector {
+            operator fn plus(other: Vector) -> Vector = other
+        }
+        "#;
+        let src_info = SrcInfo {
+            filename: "test.ld".to_string(),
+        };
+        let program = Parser::new(Tokenizer::new(src, src_info)).parse_program().unwrap();
+        let function = |index: usize| program.declarations[index].downcast::<FunctionDeclaration>().unwrap();
+        assert!(function(0).is_operator && function(0).name.get_name() == "times");
+        assert!(!function(1).is_operator);
+        let class = program.declarations[2].downcast::<ClassDeclaration>().unwrap();
+        let plus = class.body[0].downcast::<FunctionDeclaration>().unwrap();
+        assert!(plus.is_operator && plus.name.get_name() == "plus");
+    }
 }
@@ -10,7 +10,7 @@ impl Parser {
             self.token_buffer.is_identifier_of("operator") && {
                 let mut buffer = self.token_buffer.sub_token_buffer(1);
                 buffer.skip_whitespaces();
-                buffer.is_identifier_of("fun")
+                buffer.is_identifier_of("fn")
             }
         )
     }
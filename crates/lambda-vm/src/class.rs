@@ -0,0 +1,65 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use crate::function::Function;
+
+pub struct Class {
+    pub name: String,
+    pub super_class: Option<Rc<Class>>,
+    pub interfaces: Vec<Rc<Class>>,
+    pub fields: Vec<String>, // 本类声明的字段
+    pub slots: Vec<String>, // 对象的字段槽位：父类的槽位在前，本类声明的字段依次追加
+    pub slot_indices: HashMap<String, usize>, // 字段名 → 槽位，与父类同名的字段指向子类声明的槽位
+    pub supertypes: HashSet<String>, // 本类和所有父类、接口的名字，类型检查只需一次查找
+    pub methods: HashMap<String, Rc<Function>>, // 本类声明的方法
+    pub vtable: HashMap<String, Rc<Function>>, // 方法名 → 实际调用的方法，包括继承的方法和接口中带有实现的方法
+}
+
+impl Class {
+    pub fn new(
+        name: String, super_class: Option<Rc<Class>>, interfaces: Vec<Rc<Class>>, fields: Vec<String>,
+        methods: HashMap<String, Rc<Function>>,
+    ) -> Self {
+        let mut slots = super_class.as_ref().map(|class| class.slots.clone()).unwrap_or_default();
+        let mut slot_indices = super_class.as_ref().map(|class| class.slot_indices.clone()).unwrap_or_default();
+        for field in &fields {
+            slot_indices.insert(field.clone(), slots.len());
+            slots.push(field.clone());
+        }
+        let mut supertypes = HashSet::from([name.clone()]);
+        for class in super_class.iter().chain(&interfaces) {
+            supertypes.extend(class.supertypes.iter().cloned());
+        }
+        // 先沿继承链取声明的方法，再从最近的类开始取接口中的方法
+        let mut vtable = methods.clone();
+        let mut current = super_class.as_ref();
+        while let Some(class) = current {
+            for (name, method) in &class.methods {
+                vtable.entry(name.clone()).or_insert_with(|| method.clone());
+            }
+            current = class.super_class.as_ref();
+        }
+        let ancestors = std::iter::successors(super_class.as_ref(), |class| class.super_class.as_ref());
+        for interface in interfaces.iter().chain(ancestors.flat_map(|class| &class.interfaces)) {
+            for (name, method) in &interface.vtable {
+                vtable.entry(name.clone()).or_insert_with(|| method.clone());
+            }
+        }
+        Class { name, super_class, interfaces, fields, slots, slot_indices, supertypes, methods, vtable }
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Rc<Function>> { self.vtable.get(name).cloned() }
+
+    pub fn is_subclass_of(&self, name: &str) -> bool { self.supertypes.contains(name) }
+
+    pub fn get_slot(&self, field: &str) -> Option<usize> { self.slot_indices.get(field).copied() }
+
+    // 包含父类字段在内的全部字段
+    pub fn get_all_fields(&self) -> Vec<String> { self.slots.clone() }
+}
+
+impl Debug for Class {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "class {}", self.name)
+    }
+}
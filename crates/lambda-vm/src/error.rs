@@ -0,0 +1,83 @@
+use std::fmt::{Debug, Display, Formatter};
+use crate::value::Value;
+
+#[derive(Clone)]
+pub struct StackTraceElement {
+    pub function: String,
+    pub offset: usize,
+    pub source_file: Option<String>,
+    pub position: Option<(usize, usize)>, // 行和列，没有行号表时为 None
+    pub variables: Vec<(String, Value)>, // 出错时有效的局部变量
+}
+
+impl StackTraceElement {
+    // 形如 `a = 3, b = 5`
+    pub fn describe_variables(&self) -> String {
+        self.variables.iter().map(|(name, value)| format!("{} = {}", name, value)).collect::<Vec<_>>().join(", ")
+    }
+}
+
+impl Display for StackTraceElement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.position {
+            Some((line, column)) => {
+                let source_file = self.source_file.as_deref().unwrap_or("<unknown>");
+                write!(f, "at {}({}:{}:{})", self.function, source_file, line, column)
+            }
+            None => write!(f, "at {}(@{:04})", self.function, self.offset),
+        }
+    }
+}
+
+pub struct RuntimeError {
+    pub message: String,
+    pub stack_trace: Vec<StackTraceElement>,
+    pub exception: Option<Value>, // 由 Throw 抛出的值，虚拟机内部错误为 None
+}
+
+impl RuntimeError {
+    pub fn new(message: &str) -> Self {
+        RuntimeError { message: message.to_string(), stack_trace: Vec::new(), exception: None }
+    }
+
+    // 对象的 message 字段会出现在报告中
+    pub fn exception(value: Value) -> Self {
+        let message = match &value {
+            Value::Object(object) => match object.borrow().get_field("message") {
+                Some(message) if !message.is_null() => format!("{}: {}", object.borrow().class.name, message),
+                _ => object.borrow().class.name.clone(),
+            },
+            value => value.to_string(),
+        };
+        RuntimeError {
+            message: format!("Uncaught exception: {}", message),
+            stack_trace: Vec::new(),
+            exception: Some(value),
+        }
+    }
+
+    pub fn format(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "RuntimeError: {}", self.message)?;
+        for element in &self.stack_trace {
+            writeln!(f, "    {}", element)?;
+            if !element.variables.is_empty() {
+                writeln!(f, "        {}", element.describe_variables())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Debug for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.format(f)
+    }
+}
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.format(f)
+    }
+}
+impl std::error::Error for RuntimeError {}
+
+pub type RuntimeResult<T> = Result<T, RuntimeError>;
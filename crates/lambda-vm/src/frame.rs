@@ -0,0 +1,52 @@
+use std::rc::Rc;
+use crate::error::{RuntimeError, RuntimeResult};
+use crate::function::Function;
+use crate::value::Value;
+
+pub struct Frame {
+    pub function: Rc<Function>,
+    pub pc: usize, // 下一条指令的下标
+    pub locals: Vec<Value>,
+    pub stack: Vec<Value>,
+}
+
+impl Frame {
+    pub fn new(function: Rc<Function>, arguments: Vec<Value>) -> Self {
+        let mut locals = arguments;
+        locals.resize(function.locals, Value::Null);
+        Frame { function, pc: 0, locals, stack: Vec::new() }
+    }
+
+    pub fn push(&mut self, value: Value) { self.stack.push(value); }
+
+    pub fn pop(&mut self) -> RuntimeResult<Value> {
+        self.stack.pop().ok_or_else(|| RuntimeError::new("Operand stack underflow"))
+    }
+
+    pub fn peek(&self) -> RuntimeResult<&Value> {
+        self.stack.last().ok_or_else(|| RuntimeError::new("Operand stack underflow"))
+    }
+
+    pub fn get_local(&self, index: usize) -> RuntimeResult<Value> {
+        self.locals.get(index).cloned().ok_or_else(|| {
+            RuntimeError::new(format!("Local variable index {} out of range", index).as_str())
+        })
+    }
+
+    // 当前指令处有效的局部变量名和值，没有局部变量表时为空
+    pub fn get_variables(&self) -> Vec<(String, Value)> {
+        self.function.get_variables(self.pc.saturating_sub(1))
+            .filter_map(|variable| Some((variable.name.clone(), self.locals.get(variable.slot)?.clone())))
+            .collect()
+    }
+
+    pub fn set_local(&mut self, index: usize, value: Value) -> RuntimeResult<()> {
+        match self.locals.get_mut(index) {
+            Some(local) => {
+                *local = value;
+                Ok(())
+            }
+            None => Err(RuntimeError::new(format!("Local variable index {} out of range", index).as_str())),
+        }
+    }
+}
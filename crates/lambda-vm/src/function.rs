@@ -0,0 +1,129 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use lambda_bytecode::bytecode::bytecode::Bytecode;
+use lambda_bytecode::bytecode::module::{get_line, LineNumber};
+use lambda_bytecode::bytecode::reader::BytecodeReader;
+use crate::class::Class;
+use crate::error::{RuntimeError, RuntimeResult};
+use crate::native::NativeFunction;
+use crate::pool::ConstantPool;
+use crate::value::Value;
+
+pub struct Function {
+    pub name: String,
+    pub parameters: usize,
+    pub locals: usize,
+    pub operator: bool, // 由 operator fn 声明，可以作为运算符指令的回退
+    pub constants: Rc<ConstantPool>, // 所属模块的常量池
+    pub instructions: Vec<Bytecode>,
+    pub offsets: Vec<usize>, // 每条指令在字节码中的偏移，最后是字节码的长度
+    pub source_file: Option<String>,
+    pub lines: Vec<LineNumber>,
+    pub handlers: Vec<Handler>,
+    pub variables: Vec<Variable>,
+    pub native: Option<NativeFunction>, // 本地函数由宿主实现，没有字节码
+    field_cache: RefCell<Vec<Option<CachedSlot>>>, // 每条字段指令上次访问的类和槽位
+}
+
+type CachedSlot = (Rc<Class>, usize);
+
+// 异常表的一项，范围和处理代码都是指令下标
+pub struct Handler {
+    pub start: usize,
+    pub end: usize,
+    pub handler: usize,
+    pub class: Option<String>, // None 表示捕获所有异常
+}
+
+// 局部变量表的一项，有效范围 [start, end) 是指令下标
+pub struct Variable {
+    pub name: String,
+    pub value_type: String,
+    pub slot: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Function {
+    pub fn new(name: String, parameters: usize, locals: usize, constants: Rc<ConstantPool>, code: Vec<u8>) -> RuntimeResult<Self> {
+        let mut reader = BytecodeReader::new(code);
+        let mut instructions = Vec::new();
+        let mut offsets = Vec::new();
+        while reader.has_next() {
+            let offset = reader.position;
+            let instruction = reader.read_bytecode().map_err(|error| {
+                RuntimeError::new(format!("Invalid bytecode in function '{}' at offset {}: {}", name, offset, error).as_str())
+            })?;
+            offsets.push(offset);
+            instructions.push(instruction);
+        }
+        offsets.push(reader.position); // 跳到字节码末尾等同于执行到函数末尾
+        let field_cache = RefCell::new(vec![None; instructions.len()]);
+        Ok(Function {
+            name,
+            parameters,
+            locals: locals.max(parameters),
+            operator: false,
+            constants,
+            instructions,
+            offsets,
+            source_file: None,
+            lines: Vec::new(),
+            handlers: Vec::new(),
+            variables: Vec::new(),
+            native: None,
+            field_cache,
+        })
+    }
+
+    pub fn native(name: String, parameters: usize, function: NativeFunction) -> Self {
+        Function {
+            name,
+            parameters,
+            locals: parameters,
+            operator: false,
+            constants: Rc::new(ConstantPool::new(Vec::new())),
+            instructions: Vec::new(),
+            offsets: Vec::new(),
+            source_file: None,
+            lines: Vec::new(),
+            handlers: Vec::new(),
+            variables: Vec::new(),
+            native: Some(function),
+            field_cache: RefCell::new(Vec::new()),
+        }
+    }
+
+    // 字段指令的内联缓存：接收者的类与上次相同时直接使用缓存的槽位，不再按名字查找
+    pub fn get_field_slot(&self, index: usize, class: &Rc<Class>, name: &str) -> Option<usize> {
+        let mut cache = self.field_cache.borrow_mut();
+        if let Some((cached, slot)) = &cache[index] && Rc::ptr_eq(cached, class) {
+            return Some(*slot);
+        }
+        let slot = class.get_slot(name)?;
+        cache[index] = Some((class.clone(), slot));
+        Some(slot)
+    }
+
+    pub fn get_index(&self, offset: usize) -> Option<usize> { self.offsets.binary_search(&offset).ok() }
+
+    pub fn get_offset(&self, index: usize) -> usize { self.offsets.get(index).copied().unwrap_or(0) }
+
+    // 查找覆盖指定指令并能捕获该异常的处理代码
+    pub fn find_handler(&self, index: usize, exception: &Value) -> Option<usize> {
+        self.handlers.iter().find(|handler| {
+            (handler.start..handler.end).contains(&index)
+                && handler.class.as_ref().is_none_or(|class| exception.is_instance_of(class))
+        }).map(|handler| handler.handler)
+    }
+
+    // 执行到指定指令时有效的局部变量
+    pub fn get_variables(&self, index: usize) -> impl Iterator<Item = &Variable> {
+        self.variables.iter().filter(move |variable| (variable.start..variable.end).contains(&index))
+    }
+
+    // 指令偏移对应的源码行和列
+    pub fn get_position(&self, offset: usize) -> Option<(usize, usize)> {
+        get_line(&self.lines, offset).map(|line| (line.line, line.column))
+    }
+}
@@ -0,0 +1,119 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use crate::class::Class;
+use crate::value::{Object, Value};
+
+pub const INITIAL_THRESHOLD: usize = 1024; // 第一次自动回收前允许分配的对象数
+
+// 宿主代码持有的对象句柄，在释放前始终作为回收的根
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle(pub usize);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    pub objects: usize, // 堆中的对象数
+    pub slots: usize, // 这些对象的字段槽位总数
+    pub bytes: usize, // 估算的占用字节数
+    pub allocated: usize, // 累计分配的对象数
+    pub collections: usize, // 回收次数
+    pub freed: usize, // 累计回收的对象数
+}
+
+// 对象仍由引用计数管理，堆只记录分配过的对象。
+// 回收时从根标记可达对象，不可达的对象清空字段，断开环形引用后由引用计数释放
+pub struct Heap {
+    objects: Vec<Weak<RefCell<Object>>>, // 包含引用计数已经释放的对象，检查和回收时清理
+    pub threshold: usize, // 堆中存活对象数达到该值时自动回收
+    limit: usize, // 上次回收后存活对象数的两倍，存活对象多时放宽阈值，避免频繁回收
+    allocated: usize,
+    collections: usize,
+    freed: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self { Self::new() }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap { objects: Vec::new(), threshold: INITIAL_THRESHOLD, limit: 0, allocated: 0, collections: 0, freed: 0 }
+    }
+
+    pub fn allocate(&mut self, class: Rc<Class>) -> Value {
+        let object = Rc::new(RefCell::new(Object::new(class)));
+        self.objects.push(Rc::downgrade(&object));
+        self.allocated += 1;
+        Value::Object(object)
+    }
+
+    // 已经被引用计数释放的对象不计入，短命的无环对象不会触发回收
+    pub fn should_collect(&mut self) -> bool {
+        let limit = self.threshold.max(self.limit);
+        if self.objects.len() < limit {
+            return false;
+        }
+        self.objects.retain(|object| object.strong_count() > 0);
+        self.objects.len() >= limit
+    }
+
+    // 回收不可达的对象，返回回收的个数。
+    // 除了给定的根，被堆外（宿主代码、正在执行的指令）引用的对象也视为根：
+    // 它的引用计数大于堆内对象对它的引用数
+    pub fn collect<'a>(&mut self, roots: impl IntoIterator<Item = &'a Value>) -> usize {
+        let objects: Vec<Rc<RefCell<Object>>> = self.objects.iter().filter_map(Weak::upgrade).collect();
+        let indices: HashMap<*const RefCell<Object>, usize> = objects.iter().enumerate()
+            .map(|(index, object)| (Rc::as_ptr(object), index))
+            .collect();
+        let get_index = |value: &Value| match value {
+            Value::Object(object) => indices.get(&Rc::as_ptr(object)).copied(),
+            _ => None,
+        };
+        let mut references = vec![0; objects.len()];
+        let mut pending = Vec::new();
+        for (index, object) in objects.iter().enumerate() {
+            match object.try_borrow() {
+                Ok(object) => object.fields.iter().filter_map(get_index).for_each(|field| references[field] += 1),
+                Err(_) => pending.push(index), // 正在被修改的对象一定在使用中
+            }
+        }
+        // 减去上面 upgrade 产生的一个引用
+        pending.extend((0..objects.len()).filter(|index| Rc::strong_count(&objects[*index]) - 1 > references[*index]));
+        pending.extend(roots.into_iter().filter_map(get_index));
+        let mut marked = vec![false; objects.len()];
+        while let Some(index) = pending.pop() {
+            if std::mem::replace(&mut marked[index], true) {
+                continue;
+            }
+            if let Ok(object) = objects[index].try_borrow() {
+                pending.extend(object.fields.iter().filter_map(get_index));
+            }
+        }
+        // 先取出所有字段再释放，避免释放过程中访问已清空的对象
+        let mut garbage = Vec::new();
+        for (object, _) in objects.iter().zip(&marked).filter(|(_, marked)| !**marked) {
+            garbage.push(std::mem::take(&mut object.borrow_mut().fields));
+        }
+        let count = garbage.len();
+        drop(garbage);
+        drop(objects);
+        self.objects.retain(|object| object.strong_count() > 0);
+        self.limit = self.objects.len() * 2;
+        self.collections += 1;
+        self.freed += count;
+        count
+    }
+
+    pub fn get_stats(&self) -> HeapStats {
+        let objects: Vec<Rc<RefCell<Object>>> = self.objects.iter().filter_map(Weak::upgrade).collect();
+        let slots = objects.iter().map(|object| object.try_borrow().map_or(0, |object| object.fields.len())).sum();
+        HeapStats {
+            objects: objects.len(),
+            slots,
+            bytes: objects.len() * size_of::<RefCell<Object>>() + slots * size_of::<Value>(),
+            allocated: self.allocated,
+            collections: self.collections,
+            freed: self.freed,
+        }
+    }
+}
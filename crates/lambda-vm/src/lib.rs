@@ -1 +1,802 @@
+pub mod class;
+pub mod error;
+pub mod frame;
+pub mod function;
+pub mod heap;
+pub mod loader;
+pub mod native;
+pub mod operator;
+pub mod pool;
+pub mod runtime;
+pub mod value;
 pub mod vm;
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use lambda_bytecode::bytecode::archive::Archive;
+    use lambda_bytecode::bytecode::assembler::assemble_module;
+    use lambda_bytecode::bytecode::builder::BytecodeBuilder;
+    use lambda_bytecode::bytecode::bytecode::Bytecode;
+    use lambda_bytecode::bytecode::module::{ClassDefinition, FunctionDefinition, LineNumber, Module};
+    use lambda_bytecode::compiler::Compiler;
+    use lambda_parser::parser::api::Parser;
+    use lambda_parser::tokenizer::tokenizer::{SrcInfo, Tokenizer};
+    use crate::error::RuntimeError;
+    use crate::heap::Handle;
+    use crate::runtime::{IntoValue, Runtime};
+    use crate::value::{Object, Value};
+    use crate::vm::VirtualMachine;
+
+    fn assemble(instructions: Vec<Bytecode>) -> Vec<u8> {
+        let mut builder = BytecodeBuilder::new();
+        for instruction in instructions {
+            builder.write_bytecode(instruction);
+        }
+        builder.bytes
+    }
+
+    #[test]
+    fn it_works() {
+        let mut module = Module::new();
+        let point = module.add_class("Point");
+        let point_name = module.add_string("Point");
+        let x = module.add_string("x");
+        let main = module.add_string("main");
+        let choose = module.add_string("choose");
+        let choose_function = module.add_function("choose", "(?)?");
+        let yes = module.add_string("yes");
+        let no = module.add_string("no");
+        module.classes.push(ClassDefinition { name: point_name, super_class: None, interfaces: vec![], fields: vec![x], methods: vec![] });
+        // fn choose(flag) = if (flag) "yes" else "no"
+        module.functions.push(FunctionDefinition {
+            name: choose,
+            parameters: 1,
+            locals: 1,
+            code: assemble(vec![
+                Bytecode::LoadLocal(0), // 0
+                Bytecode::JumpIfFalse(7), // 2
+                Bytecode::LoadConst(yes), // 4
+                Bytecode::Return, // 6
+                Bytecode::LoadConst(no), // 7
+                Bytecode::Return, // 9
+            ]),
+            ..FunctionDefinition::default()
+        });
+        // fn main(flag) { val p = Point(); p.x = choose(flag); return p.x }
+        module.functions.push(FunctionDefinition {
+            name: main,
+            parameters: 1,
+            locals: 2,
+            code: assemble(vec![
+                Bytecode::NewObject(point),
+                Bytecode::Store(1),
+                Bytecode::LoadLocal(1),
+                Bytecode::LoadLocal(0),
+                Bytecode::Invoke(choose_function),
+                Bytecode::SetField(x),
+                Bytecode::LoadLocal(1),
+                Bytecode::GetField(x),
+                Bytecode::Return,
+            ]),
+            ..FunctionDefinition::default()
+        });
+        let mut vm = VirtualMachine::new();
+        vm.load_module(&module).unwrap();
+        let result = vm.invoke("main", vec![Value::Boolean(true)]).unwrap();
+        assert_eq!(result.as_str(), Some("yes"));
+        let result = vm.invoke("main", vec![Value::Boolean(false)]).unwrap();
+        assert_eq!(result.as_str(), Some("no"));
+    }
+
+    #[test]
+    fn uncaught_exception() {
+        let mut module = Module::new();
+        let fail = module.add_string("fail");
+        let message = module.add_string("boom");
+        module.functions.push(FunctionDefinition {
+            name: fail,
+            parameters: 0,
+            locals: 0,
+            operator: false,
+            code: assemble(vec![Bytecode::LoadConst(message), Bytecode::Throw]),
+            lines: vec![LineNumber { offset: 0, line: 2, column: 11 }, LineNumber { offset: 2, line: 2, column: 5 }],
+            handlers: vec![],
+            variables: vec![],
+        });
+        module.source_file = Some("test.ld".to_string());
+        let mut vm = VirtualMachine::new();
+        vm.load_module(&module).unwrap();
+        let error = vm.invoke("fail", vec![]).unwrap_err();
+        assert_eq!(error.message, "Uncaught exception: boom");
+        assert_eq!(error.stack_trace.len(), 1);
+        assert_eq!(error.stack_trace[0].to_string(), "at fail(test.ld:2:5)");
+        assert!(vm.frames.is_empty());
+    }
+
+    #[test]
+    fn operators() {
+        let module = assemble_module(r#"
+            class Money
+                field cents
+                method Money.plus
+
+            class Label
+                method Label.plus
+
+            operator fn Money.plus (parameters: 2, locals: 3)
+                NewObject class "Money"
+                Store 2
+                LoadLocal 2
+                LoadLocal 0
+                GetField "cents"
+                LoadLocal 1
+                GetField "cents"
+                Add
+                SetField "cents"
+                LoadLocal 2
+                Return
+
+            fn Label.plus (parameters: 2, locals: 2)
+                LoadLocal 0
+                Return
+
+            fn calculate (parameters: 2, locals: 2)
+                LoadLocal 0
+                LoadLocal 1
+                Mul
+                LoadConst 1
+                Add
+                Return
+
+            fn divide (parameters: 2, locals: 2)
+                LoadLocal 0
+                LoadLocal 1
+                Div
+                Return
+
+            fn compare (parameters: 2, locals: 2)
+                LoadLocal 0
+                LoadLocal 1
+                LessEqual
+                LoadLocal 0
+                LoadLocal 1
+                Equal
+                And
+                Not
+                Return
+
+            fn add (parameters: 2, locals: 2)
+                LoadLocal 0
+                LoadLocal 1
+                Add
+                Return
+
+            fn check (parameters: 1, locals: 1)
+                LoadLocal 0
+                JumpIfFalse end
+                LoadLocal 0
+                Return
+            end:
+        "#).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.load_module(&module).unwrap();
+        let calculate = |vm: &mut VirtualMachine, a: Value, b: Value| vm.invoke("calculate", vec![a, b]).unwrap().to_string();
+        assert_eq!(calculate(&mut vm, Value::Int(6), Value::Int(7)), "43");
+        assert_eq!(calculate(&mut vm, Value::Int(i64::MAX), Value::Int(2)), "18446744073709551615");
+        assert_eq!(calculate(&mut vm, Value::Float(0.5), Value::Int(3)), "2.5");
+        assert_eq!(vm.invoke("divide", vec![Value::Int(7), Value::Int(2)]).unwrap(), Value::Int(3));
+        assert_eq!(vm.invoke("divide", vec![Value::Int(7), Value::Int(0)]).unwrap_err().message, "Division by zero");
+        assert_eq!(vm.invoke("compare", vec![Value::Int(1), Value::Float(1.0)]).unwrap(), Value::Boolean(false));
+        assert_eq!(vm.invoke("compare", vec![Value::Int(1), Value::Int(2)]).unwrap(), Value::Boolean(true));
+        // 跳到函数末尾和执行到末尾一样返回 null
+        assert_eq!(vm.invoke("check", vec![Value::Boolean(true)]).unwrap(), Value::Boolean(true));
+        assert_eq!(vm.invoke("check", vec![Value::Boolean(false)]).unwrap(), Value::Null);
+        let add = |vm: &mut VirtualMachine, a: Value, b: Value| vm.invoke("add", vec![a, b]);
+        assert_eq!(add(&mut vm, Value::String("a".into()), Value::Int(1)).unwrap().as_str(), Some("a1"));
+        let error = add(&mut vm, Value::Boolean(true), Value::Int(1)).unwrap_err();
+        assert_eq!(error.message, "Unsupported operand types for 'plus': lambda.lang.Boolean and lambda.lang.Int");
+
+        // 对象回退到类中的 operator fn
+        let money = |vm: &mut VirtualMachine, cents: i64| {
+            let mut object = Object::new(vm.get_class("Money").unwrap());
+            object.set_field("cents", Value::Int(cents));
+            Value::Object(Rc::new(RefCell::new(object)))
+        };
+        let (a, b) = (money(&mut vm, 150), money(&mut vm, 75));
+        let sum = add(&mut vm, a, b).unwrap();
+        let Value::Object(sum) = sum else { panic!("Expected an object") };
+        assert_eq!(sum.borrow().get_field("cents"), Some(&Value::Int(225)));
+        // 没有声明为 operator fn 的同名方法不参与运算符分派
+        let label = vm.allocate(vm.get_class("Label").unwrap());
+        let error = add(&mut vm, label.clone(), label).unwrap_err();
+        assert_eq!(error.message, "Unsupported operand types for 'plus': Label and Label");
+    }
+
+    #[test]
+    fn exceptions() {
+        let source = r#"
+            class Error
+                field message
+
+            fn fail (parameters: 1, locals: 1)
+                NewObject class "Error"
+                Dup
+                LoadLocal 0
+                SetField "message"
+                Throw
+
+            fn recover (parameters: 1, locals: 1)
+            start:
+                LoadLocal 0
+                Invoke fn "fail" "(?)?"
+                Return
+            end:
+                GetField "message"
+                Return
+                catch start end end class "CLASS"
+        "#;
+        let mut vm = VirtualMachine::new();
+        vm.load_module(&assemble_module(&source.replace("CLASS", "Error")).unwrap()).unwrap();
+        let result = vm.invoke("recover", vec![Value::String("boom".into())]).unwrap();
+        assert_eq!(result.as_str(), Some("boom"));
+        assert!(vm.frames.is_empty());
+
+        let mut vm = VirtualMachine::new();
+        vm.load_module(&assemble_module(&source.replace("CLASS", "Other")).unwrap()).unwrap();
+        let error = vm.invoke("recover", vec![Value::String("boom".into())]).unwrap_err();
+        assert_eq!(error.message, "Uncaught exception: Error: boom");
+        assert!(error.exception.is_some_and(|exception| exception.is_instance_of("Error")));
+        let trace: Vec<String> = error.stack_trace.iter().map(|element| element.to_string()).collect();
+        assert_eq!(trace, vec!["at fail(@0007)", "at recover(@0002)"]);
+        assert!(vm.frames.is_empty());
+
+        // 虚拟机自身的错误不会被捕获所有异常的处理器截获
+        let module = assemble_module(r#"
+            fn divide (parameters: 2, locals: 2)
+            start:
+                LoadLocal 0
+                LoadLocal 1
+                Div
+                Return
+            end:
+                Pop
+                LoadConst "caught"
+                Return
+                catch start end end
+        "#).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.load_module(&module).unwrap();
+        let error = vm.invoke("divide", vec![Value::Int(1), Value::Int(0)]).unwrap_err();
+        assert_eq!(error.message, "Division by zero");
+        assert!(error.exception.is_none());
+        assert!(vm.frames.is_empty());
+
+        // 本地函数重入虚拟机时，栈追踪包含内层和外层的帧
+        let module = assemble_module(r#"
+            native fn "host.call" "()?"
+
+            fn fail (parameters: 0, locals: 0)
+                LoadConst 1
+                LoadConst 0
+                Div
+                Return
+
+            fn outer (parameters: 0, locals: 0)
+                Invoke fn "host.call" "()?"
+                Return
+        "#).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.natives.register("host.call", "()?", |vm, _| vm.invoke("fail", vec![]));
+        vm.load_module(&module).unwrap();
+        let error = vm.invoke("outer", vec![]).unwrap_err();
+        let trace: Vec<&str> = error.stack_trace.iter().map(|element| element.function.as_str()).collect();
+        assert_eq!(trace, vec!["fail", "outer"]);
+        assert!(vm.frames.is_empty());
+    }
+
+    #[test]
+    fn method_dispatch() {
+        let module = assemble_module(r#"
+            class Animal
+                method Animal.speak
+                method Animal.describe
+
+            class Named
+                method Named.label
+
+            class Dog : Animal, Named
+                method Dog.speak
+                method Dog.describe
+
+            fn Animal.speak (parameters: 1, locals: 1)
+                LoadConst "..."
+                Return
+
+            fn Animal.describe (parameters: 1, locals: 1)
+                LoadLocal 0
+                InvokeVirtual fn "Animal.speak" "()?" 0
+                Return
+
+            fn Named.label (parameters: 2, locals: 2)
+                LoadLocal 1
+                Return
+
+            fn Dog.speak (parameters: 1, locals: 1)
+                LoadConst "woof"
+                Return
+
+            fn Dog.describe (parameters: 1, locals: 1)
+                LoadLocal 0
+                InvokeSpecial fn "Animal.describe" "()?" 0
+                Return
+
+            fn label (parameters: 1, locals: 1)
+                LoadLocal 0
+                LoadConst "name"
+                InvokeInterface fn "Named.label" "(?)?" 1
+                Return
+        "#).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.load_module(&module).unwrap();
+        let new = |vm: &mut VirtualMachine, class: &str| {
+            Value::Object(Rc::new(RefCell::new(Object::new(vm.get_class(class).unwrap()))))
+        };
+        let (animal, dog) = (new(&mut vm, "Animal"), new(&mut vm, "Dog"));
+        // super 调用不经过动态分派，但其中的虚调用仍按实际类型分派
+        assert_eq!(vm.invoke("Animal.describe", vec![animal.clone()]).unwrap().as_str(), Some("..."));
+        assert_eq!(vm.invoke("Dog.describe", vec![dog.clone()]).unwrap().as_str(), Some("woof"));
+        assert_eq!(vm.invoke("label", vec![dog]).unwrap().as_str(), Some("name"));
+        assert_eq!(vm.invoke("label", vec![animal]).unwrap_err().message, "Animal does not implement interface Named");
+        assert_eq!(vm.invoke("Animal.describe", vec![Value::Null]).unwrap_err().message, "Null pointer dereference");
+        assert!(vm.frames.is_empty());
+    }
+
+    #[test]
+    fn local_variables() {
+        let module = assemble_module(r#"
+            fn divide (parameters: 2, locals: 2)
+            start:
+                LoadLocal 0
+                LoadLocal 1
+                Div
+                Return
+            end:
+                local start end 0 "a" "lambda.lang.Int"
+                local start end 1 "b" "lambda.lang.Int"
+        "#).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.load_module(&module).unwrap();
+        let error = vm.invoke("divide", vec![Value::Int(3), Value::Int(0)]).unwrap_err();
+        assert_eq!(error.stack_trace[0].describe_variables(), "a = 3, b = 0");
+        assert!(error.to_string().contains("at divide(@0004)\n        a = 3, b = 0\n"));
+    }
+
+    #[test]
+    fn archive() {
+        // 子类所在的模块排在前面，类在使用时才链接，加载顺序不影响结果
+        let dog = assemble_module(r#"
+            class Dog : Animal
+                method Dog.speak
+
+            fn Dog.speak (parameters: 1, locals: 1)
+                LoadConst "woof"
+                Return
+
+            fn main (parameters: 0, locals: 0)
+                NewObject class "Dog"
+                InvokeVirtual fn "Animal.speak" "()?" 0
+                Return
+        "#).unwrap();
+        let animal = assemble_module(r#"
+            class Animal
+                method Animal.speak
+
+            fn Animal.speak (parameters: 1, locals: 1)
+                LoadConst "..."
+                Return
+        "#).unwrap();
+        let mut archive = Archive::new();
+        archive.add_module("dog.ld", dog.clone());
+        archive.add_module("animal.ld", animal);
+        assert_eq!(VirtualMachine::new().run(&archive).unwrap_err().message, "Archive has no entry point");
+        archive.manifest.entry_point = Some("main".to_string());
+        let mut vm = VirtualMachine::new();
+        assert_eq!(vm.run(&archive).unwrap().as_str(), Some("woof"));
+        assert_eq!(vm.get_class("Dog").unwrap().super_class.as_ref().unwrap().name, "Animal");
+
+        let mut incomplete = Archive::new();
+        incomplete.manifest.entry_point = Some("main".to_string());
+        incomplete.add_module("dog.ld", dog);
+        assert_eq!(VirtualMachine::new().run(&incomplete).unwrap_err().message, "Unresolved class 'Animal' (super class of 'Dog')");
+    }
+
+    #[test]
+    fn singletons() {
+        let source = r#"
+            class Config
+                field name
+                method Config.<init>
+
+            fn Config.<init> (parameters: 1, locals: 1)
+                LoadLocal 0
+                LoadConst "INIT"
+                SetField "name"
+
+            fn name (parameters: 0, locals: 0)
+                GetObject class "Config"
+                GetField "name"
+                Return
+        "#;
+        // object 的实例在第一次访问时运行初始化函数，之后复用同一个实例
+        let mut vm = VirtualMachine::new();
+        vm.load_module(&assemble_module(&source.replace("INIT", "app")).unwrap()).unwrap();
+        assert_eq!(vm.invoke("name", vec![]).unwrap().as_str(), Some("app"));
+        let Value::Object(config) = vm.singletons["Config"].clone() else { panic!("Expected an object") };
+        config.borrow_mut().set_field("name", Value::String("changed".into()));
+        assert_eq!(vm.invoke("name", vec![]).unwrap().as_str(), Some("changed"));
+
+        // 初始化失败时不缓存半初始化的实例
+        let mut vm = VirtualMachine::new();
+        let source = source.replace("LoadConst \"INIT\"", "LoadConst 1\n                LoadConst 0\n                Div");
+        vm.load_module(&assemble_module(&source).unwrap()).unwrap();
+        let error = vm.invoke("name", vec![]).unwrap_err();
+        assert_eq!(error.message, "Division by zero");
+        let trace: Vec<&str> = error.stack_trace.iter().map(|element| element.function.as_str()).collect();
+        assert_eq!(trace, vec!["Config.<init>", "name"]);
+        assert!(vm.singletons.is_empty());
+        assert!(vm.frames.is_empty());
+    }
+
+    #[test]
+    fn object_model() {
+        let module = assemble_module(r#"
+            class Shape
+                field name
+
+            class Named
+
+            class Circle : Shape, Named
+                field radius
+
+            class Label
+                field text
+                field name
+
+            fn getName (parameters: 1, locals: 1)
+                LoadLocal 0
+                GetField "name"
+                Return
+
+            fn rename (parameters: 2, locals: 2)
+                LoadLocal 0
+                LoadLocal 1
+                SetField "name"
+                LoadLocal 0
+                Return
+
+            fn isNamed (parameters: 1, locals: 1)
+                LoadLocal 0
+                InstanceOf class "Named"
+                Return
+        "#).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.load_module(&module).unwrap();
+        let circle = vm.get_class("Circle").unwrap();
+        assert_eq!(circle.slots, vec!["name".to_string(), "radius".to_string()]);
+        assert_eq!(vm.get_class("Label").unwrap().get_slot("name"), Some(1));
+        assert!(circle.is_subclass_of("Shape") && circle.is_subclass_of("Named") && !circle.is_subclass_of("Label"));
+
+        // 同一条字段指令交替访问槽位不同的类，缓存失效后重新查找
+        let new = |vm: &mut VirtualMachine, class: &str| {
+            Value::Object(Rc::new(RefCell::new(Object::new(vm.get_class(class).unwrap()))))
+        };
+        for (class, name) in [("Circle", "circle"), ("Label", "label"), ("Shape", "shape"), ("Label", "other")] {
+            let object = new(&mut vm, class);
+            let object = vm.invoke("rename", vec![object, Value::String(name.into())]).unwrap();
+            assert_eq!(vm.invoke("getName", vec![object.clone()]).unwrap().as_str(), Some(name));
+            let Value::Object(object) = object else { unreachable!() };
+            assert_eq!(object.borrow().get_field("name").and_then(Value::as_str), Some(name));
+        }
+        let circle = new(&mut vm, "Circle");
+        let label = new(&mut vm, "Label");
+        assert_eq!(vm.invoke("isNamed", vec![circle]).unwrap(), Value::Boolean(true));
+        assert_eq!(vm.invoke("isNamed", vec![label]).unwrap(), Value::Boolean(false));
+        assert_eq!(vm.invoke("getName", vec![Value::Int(1)]).unwrap_err().message, "Expected an object, but got lambda.lang.Int");
+        let named = new(&mut vm, "Named");
+        let error = vm.invoke("getName", vec![named]).unwrap_err();
+        assert_eq!(error.message, "No such field 'name' in Named");
+    }
+
+    #[test]
+    fn class_loader() {
+        let dog = assemble_module(r#"
+            class Dog : Animal, Named
+                method Dog.speak
+
+            fn Dog.speak (parameters: 1, locals: 1)
+                LoadConst "woof"
+                Return
+
+            fn ghost (parameters: 0, locals: 0)
+                NewObject class "Ghost"
+                Return
+        "#).unwrap();
+        let animal = assemble_module(r#"
+            class Animal
+                method Animal.speak
+                method Animal.describe
+
+            class Named
+                method Named.label
+
+            fn Animal.speak (parameters: 1, locals: 1)
+                LoadConst "..."
+                Return
+
+            fn Animal.describe (parameters: 1, locals: 1)
+                LoadLocal 0
+                InvokeVirtual fn "Animal.speak" "()?" 0
+                Return
+
+            fn Named.label (parameters: 2, locals: 2)
+                LoadLocal 1
+                Return
+        "#).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.load_module(&dog).unwrap();
+        vm.load_module(&animal).unwrap();
+        assert!(!vm.loader.is_linked("Dog") && !vm.loader.is_linked("Animal"));
+        let class = vm.get_class("Dog").unwrap();
+        assert!(vm.loader.is_linked("Animal") && vm.loader.is_linked("Named"));
+        let method = |name: &str| class.find_method(name).map(|method| method.name.clone());
+        assert_eq!(method("speak").as_deref(), Some("Dog.speak"));
+        assert_eq!(method("describe").as_deref(), Some("Animal.describe"));
+        assert_eq!(method("label").as_deref(), Some("Named.label"));
+        assert_eq!(method("bark"), None);
+        assert!(Rc::ptr_eq(&class, &vm.get_class("Dog").unwrap()));
+
+        assert_eq!(vm.invoke("ghost", vec![]).unwrap_err().message, "Unresolved class 'Ghost'");
+        assert_eq!(vm.load_module(&animal).unwrap_err().message, "Class 'Animal' is already defined");
+        // 已经在虚方法表中的方法不能被同名函数替换
+        let patch = assemble_module("fn Dog.speak (parameters: 1, locals: 1)\n LoadConst \"meow\"\n Return").unwrap();
+        assert_eq!(vm.load_module(&patch).unwrap_err().message, "Function 'Dog.speak' is already defined");
+        let object = vm.allocate(class.clone());
+        assert_eq!(vm.invoke("Dog.speak", vec![object]).unwrap().as_str(), Some("woof"));
+
+        let cyclic = assemble_module("class A : B\nclass B : A\nclass C : Missing").unwrap();
+        vm.load_module(&cyclic).unwrap();
+        assert_eq!(vm.get_class("A").unwrap_err().message, "Cyclic inheritance involving class 'A'");
+        assert_eq!(vm.get_class("B").unwrap_err().message, "Cyclic inheritance involving class 'B'");
+        assert_eq!(vm.get_class("C").unwrap_err().message, "Unresolved class 'Missing' (super class of 'C')");
+    }
+
+    #[test]
+    fn garbage_collection() {
+        let module = assemble_module(r#"
+            class Node
+                field next
+
+            fn cycle (parameters: 0, locals: 2)
+                NewObject class "Node"
+                Store 0
+                NewObject class "Node"
+                Store 1
+                LoadLocal 0
+                LoadLocal 1
+                SetField "next"
+                LoadLocal 1
+                LoadLocal 0
+                SetField "next"
+                LoadLocal 0
+                Return
+
+            fn single (parameters: 0, locals: 0)
+                NewObject class "Node"
+                Return
+        "#).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.load_module(&module).unwrap();
+
+        // 环形引用在引用计数下不会释放，回收后清空
+        let node = vm.invoke("cycle", vec![]).unwrap();
+        let Value::Object(object) = &node else { unreachable!() };
+        let weak = Rc::downgrade(object);
+        vm.invoke("cycle", vec![]).unwrap();
+        let stats = vm.get_heap_stats();
+        assert_eq!((stats.objects, stats.slots, stats.allocated), (4, 4, 4));
+        // 宿主代码持有的对象仍然可达
+        assert_eq!(vm.gc(), 2);
+        assert!(node.is_instance_of("Node") && weak.upgrade().is_some());
+        let handle = vm.pin(node);
+        assert_eq!(vm.gc(), 0);
+        assert!(vm.get_pinned(handle).is_some_and(|node| matches!(node, Value::Object(_))));
+        vm.unpin(handle);
+        assert_eq!(vm.gc(), 2);
+        assert!(weak.upgrade().is_none());
+
+        let node = vm.invoke("cycle", vec![]).unwrap();
+        vm.globals.insert("root".to_string(), node);
+        assert_eq!(vm.gc(), 0);
+        vm.globals.remove("root");
+        assert_eq!(vm.gc(), 2);
+        let stats = vm.get_heap_stats();
+        assert_eq!((stats.objects, stats.collections, stats.freed), (0, 5, 6));
+
+        // 对象数达到阈值时自动回收
+        vm.heap.threshold = 8;
+        for _ in 0..10 {
+            vm.invoke("cycle", vec![]).unwrap();
+        }
+        let stats = vm.get_heap_stats();
+        assert!(stats.collections > 6 && stats.objects < 8, "{:?}", stats);
+        vm.gc();
+
+        // 被引用计数释放的对象不会触发回收
+        let collections = vm.get_heap_stats().collections;
+        for _ in 0..100 {
+            vm.invoke("single", vec![]).unwrap();
+        }
+        assert_eq!(vm.get_heap_stats().collections, collections);
+
+        // 存活对象多时放宽阈值，堆缩小后阈值随之恢复
+        let handles: Vec<Handle> = (0..25).map(|_| {
+            let node = vm.invoke("cycle", vec![]).unwrap();
+            vm.pin(node)
+        }).collect();
+        vm.gc();
+        let collections = vm.get_heap_stats().collections;
+        for _ in 0..20 {
+            vm.invoke("cycle", vec![]).unwrap();
+        }
+        assert_eq!(vm.get_heap_stats().collections, collections);
+        for handle in handles {
+            vm.unpin(handle);
+        }
+        vm.gc();
+        let collections = vm.get_heap_stats().collections;
+        for _ in 0..10 {
+            vm.invoke("cycle", vec![]).unwrap();
+        }
+        assert!(vm.get_heap_stats().collections > collections);
+    }
+
+    #[test]
+    fn natives() {
+        let module = assemble_module(r#"
+            native fn "host.add" "(lambda.lang.Int,lambda.lang.Int)lambda.lang.Int"
+            native fn "host.fail" "()?"
+
+            fn sum (parameters: 0, locals: 0)
+                LoadConst 1
+                LoadConst 2
+                Invoke fn "host.add" "(lambda.lang.Int,lambda.lang.Int)lambda.lang.Int"
+                Return
+        "#).unwrap();
+        // 未注册和签名不兼容的声明一起报告，模块不会被加载
+        let mut vm = VirtualMachine::new();
+        vm.natives.register("host.add", "(?)?", |_, _| Ok(Value::Null));
+        assert_eq!(vm.load_module(&module).unwrap_err().message, concat!(
+            "Unresolved native functions: host.add (lambda.lang.Int,lambda.lang.Int)lambda.lang.Int (registered as (?)?), ",
+            "host.fail ()?"
+        ));
+        assert!(vm.get_function("sum").is_err());
+
+        vm.natives.register("host.add", "(lambda.lang.Int,?)lambda.lang.Int", |_, arguments| match arguments.as_slice() {
+            [Value::Int(a), Value::Int(b)] => Ok(Value::Int(a + b)),
+            _ => Err(RuntimeError::new("Expected two integers")),
+        });
+        vm.natives.register("host.fail", "()?", |vm, _| vm.invoke("host.add", vec![Value::Null, Value::Int(1)]));
+        vm.load_module(&module).unwrap();
+        assert_eq!(vm.invoke("sum", vec![]).unwrap(), Value::Int(3));
+        assert_eq!(vm.invoke("host.add", vec![Value::Int(4), Value::Int(5)]).unwrap(), Value::Int(9));
+        assert_eq!(vm.invoke("host.fail", vec![]).unwrap_err().message, "Expected two integers");
+        assert!(vm.frames.is_empty());
+    }
+
+    #[test]
+    fn failed_loading() {
+        let animal = r#"
+            class Animal
+                method Animal.speak
+
+            fn Animal.speak (parameters: 1, locals: 1)
+                LoadConst "..."
+                Return
+        "#;
+        let cat = r#"
+            native fn "host.f" "()?"
+
+            class Cat : Animal
+                method Cat.speak
+
+            fn Cat.speak (parameters: 1, locals: 1)
+                Invoke fn "host.f" "()?"
+                Return
+        "#;
+        let mut vm = VirtualMachine::new();
+        vm.natives.register("host.f", "()?", |_, _| Ok(Value::String(Rc::from("meow"))));
+        vm.load_module(&assemble_module(animal).unwrap()).unwrap();
+        // 重复定义的类使整个模块加载失败，之前检查过的本地函数和类都不会留下
+        let broken = assemble_module(&format!("{}\n{}", cat, animal)).unwrap();
+        assert_eq!(vm.load_module(&broken).unwrap_err().message, "Class 'Animal' is already defined");
+        assert!(vm.get_function("host.f").is_err());
+        assert!(!vm.loader.is_defined("Cat"));
+
+        vm.load_module(&assemble_module(cat).unwrap()).unwrap();
+        let object = vm.allocate(vm.get_class("Cat").unwrap());
+        assert_eq!(vm.invoke("Cat.speak", vec![object]).unwrap().as_str(), Some("meow"));
+    }
+
+    #[test]
+    fn runtime() {
+        struct Point { x: i64, y: i64 }
+        let source = r#"
+        package app
+
+        native fn describe(point: lambda.lang.Any) -> lambda.lang.String
+        fn add(a: lambda.lang.Int, b: lambda.lang.Int) -> lambda.lang.Int = a + b
+        fn choose(flag: lambda.lang.Boolean, a: lambda.lang.String, b: lambda.lang.String) -> lambda.lang.String = if (flag) a else b
+        fn label(point: lambda.lang.Any) -> lambda.lang.String = describe(point)
+        fn identity(value: lambda.lang.Any) -> lambda.lang.Any = value
+        fn greeting() -> lambda.lang.String = prefix
+        "#;
+        let mut runtime = Runtime::new();
+        assert!(runtime.load_source("app.ld", source).unwrap_err().message.starts_with("Unresolved native functions: app.describe"));
+        runtime.register("app.describe", "(?)lambda.lang.String", |_, arguments| {
+            match arguments[0].as_host::<Point>() {
+                Some(point) => Ok(format!("({}, {})", point.x, point.y).into_value()),
+                None => Err(RuntimeError::new("Expected a point")),
+            }
+        });
+        runtime.load_source("app.ld", source).unwrap();
+        assert_eq!(runtime.call::<i64>("add", (2, 3i64)).unwrap(), 5);
+        assert_eq!(runtime.call::<String>("app.choose", (false, "yes", "no".to_string())).unwrap(), "no");
+        assert_eq!(runtime.call::<Option<String>>("identity", (None::<i64>,)).unwrap(), None);
+        let point = Value::host("app.Point", Point { x: 1, y: 2 });
+        assert_eq!(runtime.call::<String>("label", (point.clone(),)).unwrap(), "(1, 2)");
+        assert_eq!(runtime.call::<Value>("identity", vec![point.clone()]).unwrap(), point);
+        assert_eq!(point.get_class_name(), "app.Point");
+        assert_eq!(runtime.call::<String>("label", (1,)).unwrap_err().message, "Expected a point");
+
+        // 脚本中未声明的标识符读取宿主设置的全局变量
+        runtime.set_global("app.prefix", "Hello");
+        assert_eq!(runtime.call::<String>("greeting", ()).unwrap(), "Hello");
+        assert_eq!(runtime.call::<bool>("greeting", ()).unwrap_err().message, "Expected lambda.lang.Boolean, but got lambda.lang.String");
+        assert_eq!(runtime.call::<i64>("missing", ()).unwrap_err().message, "Unresolved function 'missing'");
+
+        // 编译好的模块与源码加载到同一个运行时，同名函数需要带上包名
+        let program = Parser::new(Tokenizer::new("package tools\nfn add(a: lambda.lang.Int, b: lambda.lang.Int) -> lambda.lang.Int = a - b", SrcInfo {
+            filename: "tools.ld".to_string(),
+        })).parse_program().unwrap();
+        let mut builder = BytecodeBuilder::new();
+        builder.write_module(&Compiler::compile(&program, "tools.ld").unwrap()).unwrap();
+        runtime.load_bytes(builder.bytes).unwrap();
+        assert_eq!(runtime.call::<i64>("add", (2, 3)).unwrap_err().message, "Ambiguous function 'add': app.add, tools.add");
+        assert_eq!(runtime.call::<i64>("tools.add", (2, 3)).unwrap(), -1);
+
+        // 归档中的包可以省略，入口函数由 run 调用
+        assert_eq!(runtime.run::<String>().unwrap_err().message, "No archive with an entry point has been loaded");
+        let program = Parser::new(Tokenizer::new("package game\nfn start() -> lambda.lang.String = \"started\"\nfn score() -> lambda.lang.Int = 42", SrcInfo {
+            filename: "game.ld".to_string(),
+        })).parse_program().unwrap();
+        let mut archive = Archive::new();
+        archive.add_module("game.ld", Compiler::compile(&program, "game.ld").unwrap());
+        archive.add_module("clock.ld", assemble_module("package clock\nnative fn \"clock.now\" \"()?\"").unwrap());
+        archive.manifest.entry_point = Some("game.start".to_string());
+        let mut builder = BytecodeBuilder::new();
+        builder.write_archive(&archive).unwrap();
+        // 第二个模块加载失败时第一个模块也不会留下，修正后可以重新加载同一个归档
+        let error = runtime.load_bytes(builder.bytes.clone()).unwrap_err();
+        assert_eq!(error.message, "clock.ld: Unresolved native functions: clock.now ()?");
+        assert_eq!(runtime.call::<i64>("score", ()).unwrap_err().message, "Unresolved function 'score'");
+        assert!(runtime.run::<String>().is_err());
+        runtime.register("clock.now", "()?", |_, _| Ok(Value::Int(0)));
+        runtime.load_bytes(builder.bytes).unwrap();
+        assert_eq!(runtime.run::<String>().unwrap(), "started");
+        assert_eq!(runtime.call::<i64>("score", ()).unwrap(), 42);
+        assert!(runtime.load_bytes(vec![1, 2, 3]).unwrap_err().message.starts_with("Invalid bytecode"));
+        assert!(runtime.load_source("broken.ld", "package broken\nfn (").unwrap_err().message.contains("broken.ld"));
+    }
+}
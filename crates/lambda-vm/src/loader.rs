@@ -0,0 +1,83 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use crate::class::Class;
+use crate::error::{RuntimeError, RuntimeResult};
+use crate::function::Function;
+
+// 从模块中读取的类定义，常量池引用都已换成名字
+pub struct ClassEntry {
+    pub name: String,
+    pub super_class: Option<String>,
+    pub interfaces: Vec<String>,
+    pub fields: Vec<String>,
+    pub methods: Vec<String>, // 方法的函数全名
+}
+
+// 加载模块时只登记类定义，第一次使用时才链接父类、接口并生成虚方法表
+#[derive(Default)]
+pub struct ClassLoader {
+    pub definitions: HashMap<String, ClassEntry>,
+    classes: RefCell<HashMap<String, Rc<Class>>>, // 已链接的类
+    linking: RefCell<HashSet<String>>, // 正在链接的类，用于发现循环继承
+}
+
+impl ClassLoader {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn define(&mut self, entry: ClassEntry) -> RuntimeResult<()> {
+        if self.definitions.contains_key(&entry.name) {
+            return Err(RuntimeError::new(format!("Class '{}' is already defined", entry.name).as_str()));
+        }
+        self.definitions.insert(entry.name.clone(), entry);
+        Ok(())
+    }
+
+    pub fn is_defined(&self, name: &str) -> bool { self.definitions.contains_key(name) }
+
+    pub fn is_linked(&self, name: &str) -> bool { self.classes.borrow().contains_key(name) }
+
+    // 返回链接好的类，方法从 functions 中查找
+    pub fn load_class(&self, name: &str, functions: &HashMap<String, Rc<Function>>) -> RuntimeResult<Rc<Class>> {
+        if let Some(class) = self.classes.borrow().get(name) {
+            return Ok(class.clone());
+        }
+        let entry = self.definitions.get(name).ok_or_else(|| {
+            RuntimeError::new(format!("Unresolved class '{}'", name).as_str())
+        })?;
+        if !self.linking.borrow_mut().insert(name.to_string()) {
+            return Err(RuntimeError::new(format!("Cyclic inheritance involving class '{}'", name).as_str()));
+        }
+        let class = self.link(entry, functions);
+        self.linking.borrow_mut().remove(name);
+        let class = Rc::new(class?);
+        self.classes.borrow_mut().insert(name.to_string(), class.clone());
+        Ok(class)
+    }
+
+    fn link(&self, entry: &ClassEntry, functions: &HashMap<String, Rc<Function>>) -> RuntimeResult<Class> {
+        let resolve = |name: &str, relation: &str| self.load_class(name, functions).map_err(|error| {
+            match self.is_defined(name) {
+                true => error,
+                false => RuntimeError::new(format!("Unresolved class '{}' ({} of '{}')", name, relation, entry.name).as_str()),
+            }
+        });
+        let super_class = match &entry.super_class {
+            Some(name) => Some(resolve(name, "super class")?),
+            None => None,
+        };
+        let mut interfaces = Vec::new();
+        for name in &entry.interfaces {
+            interfaces.push(resolve(name, "interface")?);
+        }
+        let mut methods = HashMap::new();
+        for name in &entry.methods {
+            let function = functions.get(name).ok_or_else(|| {
+                RuntimeError::new(format!("Unresolved method '{}' of class '{}'", name, entry.name).as_str())
+            })?;
+            let method = name.rsplit_once('.').map_or(name.as_str(), |(_, method)| method);
+            methods.insert(method.to_string(), function.clone());
+        }
+        Ok(Class::new(entry.name.clone(), super_class, interfaces, entry.fields.clone(), methods))
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::error::RuntimeResult;
+use crate::value::Value;
+use crate::vm::VirtualMachine;
+
+// 宿主实现的函数，参数个数在链接时已按签名检查
+pub type NativeFunction = Rc<dyn Fn(&mut VirtualMachine, Vec<Value>) -> RuntimeResult<Value>>;
+
+#[derive(Clone)]
+pub struct Native {
+    pub signature: String, // `(T1,T2)R` 形式，`?` 表示任意类型
+    pub function: NativeFunction,
+}
+
+// 宿主按全限定名注册本地函数，加载模块时与 `native fn` 声明链接
+#[derive(Default)]
+pub struct NativeRegistry {
+    natives: HashMap<String, Native>,
+}
+
+impl NativeRegistry {
+    pub fn new() -> Self { Self::default() }
+
+    // 同名的函数会被替换
+    pub fn register<F>(&mut self, name: &str, signature: &str, function: F)
+    where
+        F: Fn(&mut VirtualMachine, Vec<Value>) -> RuntimeResult<Value> + 'static,
+    {
+        self.natives.insert(name.to_string(), Native { signature: signature.to_string(), function: Rc::new(function) });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Native> { self.natives.get(name) }
+
+    pub fn contains(&self, name: &str) -> bool { self.natives.contains_key(name) }
+}
+
+// 把 `(T1,T2)R` 形式的签名拆成参数类型和返回类型
+fn split_signature(signature: &str) -> Option<(Vec<&str>, &str)> {
+    let (parameters, return_type) = signature.strip_prefix('(')?.split_once(')')?;
+    let parameters = if parameters.is_empty() { Vec::new() } else { parameters.split(',').collect() };
+    Some((parameters, return_type))
+}
+
+// 参数个数相同，且每个类型相同或者其中一方是 `?`
+pub fn is_compatible(declared: &str, registered: &str) -> bool {
+    let (Some((declared, declared_return)), Some((registered, registered_return))) =
+        (split_signature(declared), split_signature(registered)) else {
+        return false;
+    };
+    let matches = |a: &str, b: &str| a == b || a == "?" || b == "?";
+    declared.len() == registered.len()
+        && declared.iter().zip(&registered).all(|(a, b)| matches(a, b))
+        && matches(declared_return, registered_return)
+}
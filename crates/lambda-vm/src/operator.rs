@@ -0,0 +1,284 @@
+use std::cmp::Ordering;
+use std::rc::Rc;
+use bigdecimal::{BigDecimal, FromPrimitive, One, ToPrimitive, Zero};
+use bigdecimal::num_bigint::BigInt;
+use lambda_bytecode::bytecode::bytecode::Bytecode;
+use crate::error::{RuntimeError, RuntimeResult};
+use crate::value::Value;
+
+// 数值运算前按 Int < BigInt < Float < BigDecimal 提升到同一种表示
+#[derive(Clone)]
+enum Number {
+    Int(i64),
+    BigInt(BigInt),
+    Float(f64),
+    BigDecimal(BigDecimal),
+}
+
+impl Number {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Int(value) => Some(Number::Int(*value)),
+            Value::BigInt(value) => Some(Number::BigInt(value.as_ref().clone())),
+            Value::Float(value) => Some(Number::Float(*value)),
+            Value::BigDecimal(value) => Some(Number::BigDecimal(value.as_ref().clone())),
+            _ => None,
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Number::Int(_) => 0,
+            Number::BigInt(_) => 1,
+            Number::Float(_) => 2,
+            Number::BigDecimal(_) => 3,
+        }
+    }
+
+    fn to_big_int(&self) -> BigInt {
+        match self {
+            Number::Int(value) => BigInt::from(*value),
+            Number::BigInt(value) => value.clone(),
+            _ => unreachable!("Not an integer"),
+        }
+    }
+
+    fn to_float(&self) -> f64 {
+        match self {
+            Number::Int(value) => *value as f64,
+            Number::BigInt(value) => value.to_f64().unwrap_or(f64::NAN),
+            Number::Float(value) => *value,
+            Number::BigDecimal(value) => value.to_f64().unwrap_or(f64::NAN),
+        }
+    }
+
+    fn to_big_decimal(&self) -> RuntimeResult<BigDecimal> {
+        match self {
+            Number::Int(value) => Ok(BigDecimal::from(*value)),
+            Number::BigInt(value) => Ok(BigDecimal::from(value.clone())),
+            Number::Float(value) => BigDecimal::from_f64(*value).ok_or_else(|| {
+                RuntimeError::new(format!("Cannot convert {:?} to lambda.lang.BigDecimal", value).as_str())
+            }),
+            Number::BigDecimal(value) => Ok(value.clone()),
+        }
+    }
+}
+
+// 能用 Int 表示的大整数收窄为 Int
+fn big_int_value(value: BigInt) -> Value {
+    match value.to_i64() {
+        Some(value) => Value::Int(value),
+        None => Value::BigInt(Rc::new(value)),
+    }
+}
+
+fn division_by_zero() -> RuntimeError { RuntimeError::new("Division by zero") }
+
+fn get_exponent(exponent: &BigInt) -> RuntimeResult<u32> {
+    exponent.to_u32().ok_or_else(|| RuntimeError::new(format!("Exponent {} is too large", exponent).as_str()))
+}
+
+fn big_int_arithmetic(operator: &Bytecode, left: BigInt, right: BigInt) -> RuntimeResult<Value> {
+    let zero = BigInt::zero();
+    match operator {
+        Bytecode::Add => Ok(big_int_value(left + right)),
+        Bytecode::Sub => Ok(big_int_value(left - right)),
+        Bytecode::Mul => Ok(big_int_value(left * right)),
+        Bytecode::Div | Bytecode::Mod if right == zero => Err(division_by_zero()),
+        Bytecode::Div => Ok(big_int_value(left / right)),
+        Bytecode::Mod => Ok(big_int_value(left % right)),
+        Bytecode::Pow if right < zero => {
+            // 负指数的结果不是整数
+            let value = Number::BigInt(left).to_float().powf(Number::BigInt(right).to_float());
+            Ok(Value::Float(value))
+        }
+        Bytecode::Pow => Ok(big_int_value(left.pow(get_exponent(&right)?))),
+        Bytecode::And => Ok(big_int_value(left & right)),
+        Bytecode::Or => Ok(big_int_value(left | right)),
+        _ => unreachable!("Not an arithmetic operator"),
+    }
+}
+
+fn int_arithmetic(operator: &Bytecode, left: i64, right: i64) -> RuntimeResult<Value> {
+    let result = match operator {
+        Bytecode::Add => left.checked_add(right),
+        Bytecode::Sub => left.checked_sub(right),
+        Bytecode::Mul => left.checked_mul(right),
+        Bytecode::Div | Bytecode::Mod if right == 0 => return Err(division_by_zero()),
+        Bytecode::Div => left.checked_div(right),
+        Bytecode::Mod => left.checked_rem(right),
+        Bytecode::Pow => u32::try_from(right).ok().and_then(|right| left.checked_pow(right)),
+        Bytecode::And => Some(left & right),
+        Bytecode::Or => Some(left | right),
+        _ => unreachable!("Not an arithmetic operator"),
+    };
+    match result {
+        Some(value) => Ok(Value::Int(value)),
+        None => big_int_arithmetic(operator, BigInt::from(left), BigInt::from(right)), // 溢出时改用 BigInt
+    }
+}
+
+fn big_decimal_pow(base: BigDecimal, exponent: &BigDecimal) -> RuntimeResult<BigDecimal> {
+    if !exponent.is_integer() {
+        return Err(RuntimeError::new("Exponent of lambda.lang.BigDecimal must be an integer"));
+    }
+    let (exponent, _) = exponent.with_scale(0).into_bigint_and_exponent();
+    let mut remaining = get_exponent(&BigInt::from(exponent.magnitude().clone()))?;
+    let mut result = BigDecimal::one();
+    let mut square = base;
+    while remaining > 0 {
+        if remaining & 1 == 1 {
+            result *= &square;
+        }
+        square = square.square();
+        remaining >>= 1;
+    }
+    if exponent < BigInt::zero() {
+        if result.is_zero() {
+            return Err(division_by_zero());
+        }
+        result = BigDecimal::one() / result;
+    }
+    Ok(result)
+}
+
+fn arithmetic(operator: &Bytecode, left: Number, right: Number) -> RuntimeResult<Value> {
+    match left.rank().max(right.rank()) {
+        0 => match (left, right) {
+            (Number::Int(left), Number::Int(right)) => int_arithmetic(operator, left, right),
+            _ => unreachable!(),
+        },
+        1 => big_int_arithmetic(operator, left.to_big_int(), right.to_big_int()),
+        2 => {
+            let (left, right) = (left.to_float(), right.to_float());
+            Ok(Value::Float(match operator {
+                Bytecode::Add => left + right,
+                Bytecode::Sub => left - right,
+                Bytecode::Mul => left * right,
+                Bytecode::Div => left / right,
+                Bytecode::Mod => left % right,
+                Bytecode::Pow => left.powf(right),
+                _ => return Err(unsupported(operator, &Value::Float(left), &Value::Float(right))),
+            }))
+        }
+        _ => {
+            let (left, right) = (left.to_big_decimal()?, right.to_big_decimal()?);
+            Ok(Value::BigDecimal(Rc::new(match operator {
+                Bytecode::Add => left + right,
+                Bytecode::Sub => left - right,
+                Bytecode::Mul => left * right,
+                Bytecode::Div | Bytecode::Mod if right.is_zero() => return Err(division_by_zero()),
+                Bytecode::Div => left / right,
+                Bytecode::Mod => left % right,
+                Bytecode::Pow => big_decimal_pow(left, &right)?,
+                _ => return Err(unsupported(
+                    operator, &Value::BigDecimal(Rc::new(left)), &Value::BigDecimal(Rc::new(right))
+                )),
+            })))
+        }
+    }
+}
+
+fn compare_numbers(left: &Number, right: &Number) -> RuntimeResult<Option<Ordering>> {
+    Ok(match left.rank().max(right.rank()) {
+        0 | 1 => Some(left.to_big_int().cmp(&right.to_big_int())),
+        2 => left.to_float().partial_cmp(&right.to_float()), // NaN 与任何数都不可比较
+        _ => Some(left.to_big_decimal()?.cmp(&right.to_big_decimal()?)),
+    })
+}
+
+// 不同表示的数值按数值比较，其余按值比较，对象比较引用
+fn values_equal(left: &Value, right: &Value) -> RuntimeResult<bool> {
+    match (Number::from_value(left), Number::from_value(right)) {
+        (Some(left), Some(right)) => Ok(compare_numbers(&left, &right)? == Some(Ordering::Equal)),
+        _ => Ok(left == right),
+    }
+}
+
+fn compare(left: &Value, right: &Value) -> Option<RuntimeResult<Option<Ordering>>> {
+    match (left, right) {
+        (Value::String(left), Value::String(right)) => Some(Ok(left.partial_cmp(right))),
+        (Value::Char(left), Value::Char(right)) => Some(Ok(left.partial_cmp(right))),
+        _ => match (Number::from_value(left), Number::from_value(right)) {
+            (Some(left), Some(right)) => Some(compare_numbers(&left, &right)),
+            _ => None,
+        },
+    }
+}
+
+fn unsupported(operator: &Bytecode, left: &Value, right: &Value) -> RuntimeError {
+    RuntimeError::new(format!(
+        "Unsupported operand types for '{}': {} and {}",
+        operator.get_operator_function_name().unwrap_or(operator.get_name()), left.get_class_name(), right.get_class_name()
+    ).as_str())
+}
+
+// 内置类型之间的二元运算；左操作数是对象时返回 None，由虚拟机查找运算符函数
+pub fn apply_binary(operator: &Bytecode, left: &Value, right: &Value) -> Option<RuntimeResult<Value>> {
+    if let Value::Object(_) = left {
+        return None;
+    }
+    Some(match operator {
+        Bytecode::Equal | Bytecode::NotEqual | Bytecode::IdentityEqual | Bytecode::IdentityNotEqual => {
+            apply_default(operator, left, right)
+        }
+        Bytecode::Less | Bytecode::LessEqual | Bytecode::Greater | Bytecode::GreaterEqual => {
+            match compare(left, right) {
+                Some(Ok(ordering)) => Ok(Value::Boolean(match operator {
+                    Bytecode::Less => ordering == Some(Ordering::Less),
+                    Bytecode::LessEqual => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    Bytecode::Greater => ordering == Some(Ordering::Greater),
+                    _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                })),
+                Some(Err(error)) => Err(error),
+                None => Err(unsupported(operator, left, right)),
+            }
+        }
+        Bytecode::Add if left.as_str().is_some() => Ok(Value::String(format!("{}{}", left, right).into())),
+        Bytecode::And | Bytecode::Or => match (left, right) {
+            (Value::Boolean(a), Value::Boolean(b)) => {
+                Ok(Value::Boolean(if let Bytecode::And = operator { *a && *b } else { *a || *b }))
+            }
+            (Value::Int(_) | Value::BigInt(_), Value::Int(_) | Value::BigInt(_)) => {
+                arithmetic(operator, Number::from_value(left).unwrap(), Number::from_value(right).unwrap())
+            }
+            _ => Err(unsupported(operator, left, right)),
+        },
+        _ => match (Number::from_value(left), Number::from_value(right)) {
+            (Some(a), Some(b)) => arithmetic(operator, a, b),
+            _ => Err(unsupported(operator, left, right)),
+        },
+    })
+}
+
+// 对象没有定义对应的运算符函数时的行为：相等比较按引用，其余报错
+pub fn apply_default(operator: &Bytecode, left: &Value, right: &Value) -> RuntimeResult<Value> {
+    match operator {
+        Bytecode::Equal => Ok(Value::Boolean(values_equal(left, right)?)),
+        Bytecode::NotEqual => Ok(Value::Boolean(!values_equal(left, right)?)),
+        Bytecode::IdentityEqual => Ok(Value::Boolean(left == right)),
+        Bytecode::IdentityNotEqual => Ok(Value::Boolean(left != right)),
+        _ => Err(unsupported(operator, left, right)),
+    }
+}
+
+// 内置类型的一元运算；操作数是对象时返回 None
+pub fn apply_unary(operator: &Bytecode, value: &Value) -> Option<RuntimeResult<Value>> {
+    let unsupported = || RuntimeError::new(format!(
+        "Unsupported operand type for '{}': {}",
+        operator.get_operator_function_name().unwrap_or(operator.get_name()), value.get_class_name()
+    ).as_str());
+    Some(match (operator, value) {
+        (_, Value::Object(_)) => return None,
+        (Bytecode::Not, Value::Boolean(value)) => Ok(Value::Boolean(!value)),
+        (Bytecode::Pos, Value::Int(_) | Value::BigInt(_) | Value::Float(_) | Value::BigDecimal(_)) => Ok(value.clone()),
+        (Bytecode::Neg, Value::Int(value)) => Ok(match value.checked_neg() {
+            Some(value) => Value::Int(value),
+            None => big_int_value(-BigInt::from(*value)),
+        }),
+        (Bytecode::Neg, Value::BigInt(value)) => Ok(big_int_value(-value.as_ref().clone())),
+        (Bytecode::Neg, Value::Float(value)) => Ok(Value::Float(-value)),
+        (Bytecode::Neg, Value::BigDecimal(value)) => Ok(Value::BigDecimal(Rc::new(-value.as_ref().clone()))),
+        _ => Err(unsupported()),
+    })
+}
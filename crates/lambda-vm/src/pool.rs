@@ -0,0 +1,75 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use lambda_bytecode::bytecode::constant::Constant;
+use crate::class::Class;
+use crate::error::{RuntimeError, RuntimeResult};
+use crate::value::Value;
+
+pub struct ConstantPool {
+    pub constants: Vec<Constant>,
+    pub values: Vec<Option<Value>>, // 可直接加载的常量预先转换为值
+    classes: RefCell<Vec<Option<Rc<Class>>>>, // 已解析的类引用
+}
+
+impl ConstantPool {
+    pub fn new(constants: Vec<Constant>) -> Self {
+        let values = constants.iter().map(|constant| match constant {
+            Constant::Int(value) => Some(Value::Int(*value)),
+            Constant::BigInt(value) => Some(Value::BigInt(Rc::new(value.clone()))),
+            Constant::Float(value) => Some(Value::Float(*value)),
+            Constant::BigDecimal(value) => Some(Value::BigDecimal(Rc::new(value.clone()))),
+            Constant::Char(value) => Some(Value::Char(*value)),
+            Constant::String(value) => Some(Value::String(Rc::from(value.as_str()))),
+            Constant::Class(_) | Constant::Function { .. } => None,
+        }).collect();
+        let classes = RefCell::new(vec![None; constants.len()]);
+        ConstantPool { constants, values, classes }
+    }
+
+    pub fn get_constant(&self, index: usize) -> RuntimeResult<&Constant> {
+        self.constants.get(index).ok_or_else(|| {
+            RuntimeError::new(format!("Constant pool index #{} out of range", index).as_str())
+        })
+    }
+
+    pub fn get_value(&self, index: usize) -> RuntimeResult<Value> {
+        self.get_constant(index)?;
+        self.values[index].clone().ok_or_else(|| {
+            RuntimeError::new(format!("Constant #{} cannot be loaded as a value", index).as_str())
+        })
+    }
+
+    pub fn get_string(&self, index: usize) -> RuntimeResult<&str> {
+        self.get_constant(index)?.as_str().ok_or_else(|| {
+            RuntimeError::new(format!("Constant #{} is not a string", index).as_str())
+        })
+    }
+
+    pub fn get_class_name(&self, index: usize) -> RuntimeResult<&str> {
+        match self.get_constant(index)? {
+            Constant::Class(name) => self.get_string(*name),
+            _ => Err(RuntimeError::new(format!("Constant #{} is not a class reference", index).as_str())),
+        }
+    }
+
+    // 类引用第一次使用时才解析，之后直接使用缓存的类
+    pub fn resolve_class<F>(&self, index: usize, load_class: F) -> RuntimeResult<Rc<Class>>
+    where
+        F: FnOnce(&str) -> RuntimeResult<Rc<Class>>,
+    {
+        if let Some(class) = self.classes.borrow().get(index).cloned().flatten() {
+            return Ok(class);
+        }
+        let class = load_class(self.get_class_name(index)?)?;
+        self.classes.borrow_mut()[index] = Some(class.clone());
+        Ok(class)
+    }
+
+    // 返回函数名和签名
+    pub fn get_function(&self, index: usize) -> RuntimeResult<(&str, &str)> {
+        match self.get_constant(index)? {
+            Constant::Function { name, signature } => Ok((self.get_string(*name)?, self.get_string(*signature)?)),
+            _ => Err(RuntimeError::new(format!("Constant #{} is not a function reference", index).as_str())),
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use std::rc::Rc;
+use bigdecimal::BigDecimal;
+use bigdecimal::num_bigint::BigInt;
+use lambda_bytecode::bytecode::archive::{Archive, ARCHIVE_MAGIC};
+use lambda_bytecode::bytecode::module::{Module, MAGIC};
+use lambda_bytecode::bytecode::reader::BytecodeReader;
+use lambda_bytecode::compiler::Compiler;
+use lambda_parser::parser::api::Parser;
+use lambda_parser::tokenizer::tokenizer::{SrcInfo, Tokenizer};
+use crate::error::{RuntimeError, RuntimeResult};
+use crate::heap::HeapStats;
+use crate::value::Value;
+use crate::vm::VirtualMachine;
+
+// 嵌入脚本的入口：加载源码或编译好的模块，按名字调用函数并在 Rust 类型和脚本值之间转换
+pub struct Runtime {
+    vm: VirtualMachine,
+    packages: Vec<String>, // 已加载模块所在的包，按加载顺序排列
+    entry_point: Option<String>, // 最近加载的带入口的归档的入口函数
+}
+
+impl Default for Runtime {
+    fn default() -> Self { Self::new() }
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        Runtime { vm: VirtualMachine::new(), packages: Vec::new(), entry_point: None }
+    }
+
+    pub fn vm(&self) -> &VirtualMachine { &self.vm }
+
+    pub fn vm_mut(&mut self) -> &mut VirtualMachine { &mut self.vm }
+
+    // 编译并加载一段源码，filename 用于错误信息和栈追踪
+    pub fn load_source(&mut self, filename: &str, source: &str) -> RuntimeResult<()> {
+        let src_info = SrcInfo { filename: filename.to_string() };
+        let mut parser = Parser::new(Tokenizer::new(source, src_info));
+        let program = parser.parse_program().map_err(|error| RuntimeError::new(error.to_string().trim_end()))?;
+        let module = Compiler::compile_with_tokens(&program, filename, &parser.token_buffer.tokens)
+            .map_err(|error| RuntimeError::new(format!("{}: {}", filename, error).as_str()))?;
+        self.load_module(&module)
+    }
+
+    // .ld 文件按源码编译，其余文件按二进制模块或归档读取
+    pub fn load_file(&mut self, path: &str) -> RuntimeResult<()> {
+        let read_error = |error: std::io::Error| RuntimeError::new(format!("Cannot read '{}': {}", path, error).as_str());
+        if path.ends_with(".ld") {
+            let source = std::fs::read_to_string(path).map_err(read_error)?;
+            return self.load_source(path, &source);
+        }
+        let bytes = std::fs::read(path).map_err(read_error)?;
+        self.load_bytes(bytes)
+    }
+
+    // 根据文件头区分模块和归档
+    pub fn load_bytes(&mut self, bytes: Vec<u8>) -> RuntimeResult<()> {
+        let decode_error = |error| RuntimeError::new(format!("Invalid bytecode: {}", error).as_str());
+        if bytes.starts_with(&ARCHIVE_MAGIC) {
+            let archive = BytecodeReader::new(bytes).read_archive().map_err(decode_error)?;
+            return self.load_archive(&archive);
+        }
+        if !bytes.starts_with(&MAGIC) {
+            return Err(RuntimeError::new("Invalid bytecode: not a module or an archive"));
+        }
+        let module = BytecodeReader::new(bytes).read_module().map_err(decode_error)?;
+        self.load_module(&module)
+    }
+
+    pub fn load_module(&mut self, module: &Module) -> RuntimeResult<()> {
+        self.vm.load_module(module)?;
+        self.add_package(&module.package);
+        Ok(())
+    }
+
+    // 清单中的入口函数可以之后用 run 调用
+    pub fn load_archive(&mut self, archive: &Archive) -> RuntimeResult<()> {
+        self.vm.load_archive(archive)?;
+        for (package, _) in &archive.manifest.packages {
+            self.add_package(package);
+        }
+        if let Some(entry_point) = &archive.manifest.entry_point {
+            self.entry_point = Some(entry_point.clone());
+        }
+        Ok(())
+    }
+
+    fn add_package(&mut self, package: &str) {
+        if !self.packages.iter().any(|loaded| loaded == package) {
+            self.packages.push(package.to_string());
+        }
+    }
+
+    // 以无参数调用已加载归档的入口函数
+    pub fn run<R: FromValue>(&mut self) -> RuntimeResult<R> {
+        let entry_point = self.entry_point.clone().ok_or_else(|| RuntimeError::new("No archive with an entry point has been loaded"))?;
+        self.call(&entry_point, ())
+    }
+
+    // 本地函数要在声明它的模块加载之前注册
+    pub fn register<F>(&mut self, name: &str, signature: &str, function: F)
+    where
+        F: Fn(&mut VirtualMachine, Vec<Value>) -> RuntimeResult<Value> + 'static,
+    {
+        self.vm.natives.register(name, signature, function);
+    }
+
+    // 脚本中未声明的标识符按所在包限定后读取全局变量，例如 `app.config`
+    pub fn set_global(&mut self, name: &str, value: impl IntoValue) {
+        self.vm.globals.insert(name.to_string(), value.into_value());
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<&Value> { self.vm.globals.get(name) }
+
+    // 函数名可以省略包名，在多个包中都找到时报错
+    pub fn resolve_function(&self, name: &str) -> RuntimeResult<String> {
+        if self.vm.functions.contains_key(name) {
+            return Ok(name.to_string());
+        }
+        let candidates: Vec<String> = self.packages.iter()
+            .map(|package| format!("{}.{}", package, name))
+            .filter(|candidate| self.vm.functions.contains_key(candidate))
+            .collect();
+        match candidates.as_slice() {
+            [] => Err(RuntimeError::new(format!("Unresolved function '{}'", name).as_str())),
+            [function] => Ok(function.clone()),
+            _ => Err(RuntimeError::new(format!("Ambiguous function '{}': {}", name, candidates.join(", ")).as_str())),
+        }
+    }
+
+    pub fn call<R: FromValue>(&mut self, name: &str, arguments: impl IntoArguments) -> RuntimeResult<R> {
+        let function = self.resolve_function(name)?;
+        R::from_value(self.vm.invoke(&function, arguments.into_arguments())?)
+    }
+
+    pub fn gc(&mut self) -> usize { self.vm.gc() }
+
+    pub fn get_heap_stats(&self) -> HeapStats { self.vm.get_heap_stats() }
+}
+
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> RuntimeResult<Self>;
+}
+
+// 调用参数，实现于 Vec<Value> 和元素实现了 IntoValue 的元组
+pub trait IntoArguments {
+    fn into_arguments(self) -> Vec<Value>;
+}
+
+fn mismatch<T>(expected: &str, value: &Value) -> RuntimeResult<T> {
+    Err(RuntimeError::new(format!("Expected {}, but got {}", expected, value.get_class_name()).as_str()))
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value { self }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> RuntimeResult<Self> { Ok(value) }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value { Value::Null }
+}
+
+// 没有返回值的函数返回 null，其余值也可以忽略
+impl FromValue for () {
+    fn from_value(_: Value) -> RuntimeResult<Self> { Ok(()) }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value { Value::Boolean(self) }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        value.as_bool().map_or_else(|| mismatch("lambda.lang.Boolean", &value), Ok)
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value { Value::Int(self) }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        match value {
+            Value::Int(value) => Ok(value),
+            value => mismatch("lambda.lang.Int", &value),
+        }
+    }
+}
+
+impl IntoValue for i32 {
+    fn into_value(self) -> Value { Value::Int(self as i64) }
+}
+
+impl FromValue for i32 {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        let value = i64::from_value(value)?;
+        i32::try_from(value).map_err(|_| RuntimeError::new(format!("Integer {} is out of range for i32", value).as_str()))
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value { Value::Float(self) }
+}
+
+// 整数可以隐式转换为浮点数
+impl FromValue for f64 {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        match value {
+            Value::Float(value) => Ok(value),
+            Value::Int(value) => Ok(value as f64),
+            value => mismatch("lambda.lang.Float", &value),
+        }
+    }
+}
+
+impl IntoValue for char {
+    fn into_value(self) -> Value { Value::Char(self) }
+}
+
+impl FromValue for char {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        match value {
+            Value::Char(value) => Ok(value),
+            value => mismatch("lambda.lang.Char", &value),
+        }
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value { Value::String(Rc::from(self)) }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value { Value::String(Rc::from(self)) }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        value.as_str().map(str::to_string).map_or_else(|| mismatch("lambda.lang.String", &value), Ok)
+    }
+}
+
+impl IntoValue for BigInt {
+    fn into_value(self) -> Value { Value::BigInt(Rc::new(self)) }
+}
+
+impl FromValue for BigInt {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        match value {
+            Value::BigInt(value) => Ok(value.as_ref().clone()),
+            Value::Int(value) => Ok(BigInt::from(value)),
+            value => mismatch("lambda.lang.BigInt", &value),
+        }
+    }
+}
+
+impl IntoValue for BigDecimal {
+    fn into_value(self) -> Value { Value::BigDecimal(Rc::new(self)) }
+}
+
+impl FromValue for BigDecimal {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        match value {
+            Value::BigDecimal(value) => Ok(value.as_ref().clone()),
+            value => mismatch("lambda.lang.BigDecimal", &value),
+        }
+    }
+}
+
+// None 对应 null
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value { self.map_or(Value::Null, IntoValue::into_value) }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        if value.is_null() { Ok(None) } else { T::from_value(value).map(Some) }
+    }
+}
+
+impl IntoArguments for Vec<Value> {
+    fn into_arguments(self) -> Vec<Value> { self }
+}
+
+macro_rules! impl_into_arguments {
+    ($($name:ident),*) => {
+        impl<$($name: IntoValue),*> IntoArguments for ($($name,)*) {
+            #[allow(non_snake_case)]
+            fn into_arguments(self) -> Vec<Value> {
+                let ($($name,)*) = self;
+                vec![$($name.into_value()),*]
+            }
+        }
+    };
+}
+
+impl_into_arguments!();
+impl_into_arguments!(A);
+impl_into_arguments!(A, B);
+impl_into_arguments!(A, B, C);
+impl_into_arguments!(A, B, C, D);
+impl_into_arguments!(A, B, C, D, E);
+impl_into_arguments!(A, B, C, D, E, F);
@@ -0,0 +1,157 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+use bigdecimal::BigDecimal;
+use bigdecimal::num_bigint::BigInt;
+use crate::class::Class;
+
+pub struct Object {
+    pub class: Rc<Class>,
+    pub fields: Vec<Value>, // 按类的槽位排列
+}
+
+impl Object {
+    pub fn new(class: Rc<Class>) -> Self {
+        let fields = vec![Value::Null; class.slots.len()];
+        Object { class, fields }
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<&Value> {
+        self.class.get_slot(name).map(|slot| &self.fields[slot])
+    }
+
+    // 字段不存在时返回 false
+    pub fn set_field(&mut self, name: &str, value: Value) -> bool {
+        match self.class.get_slot(name) {
+            Some(slot) => {
+                self.fields[slot] = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// 宿主程序交给脚本的对象。脚本只能传递和比较它，由本地函数取出原来的值
+pub struct HostObject {
+    pub class_name: String,
+    pub data: Box<dyn Any>,
+}
+
+impl HostObject {
+    pub fn new<T: Any>(class_name: &str, data: T) -> Self {
+        HostObject { class_name: class_name.to_string(), data: Box::new(data) }
+    }
+}
+
+#[derive(Clone)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Int(i64),
+    BigInt(Rc<BigInt>),
+    Float(f64),
+    BigDecimal(Rc<BigDecimal>),
+    Char(char),
+    String(Rc<str>),
+    Object(Rc<RefCell<Object>>),
+    Host(Rc<HostObject>),
+}
+
+impl Value {
+    pub fn is_null(&self) -> bool { matches!(self, Value::Null) }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn host<T: Any>(class_name: &str, data: T) -> Self { Value::Host(Rc::new(HostObject::new(class_name, data))) }
+
+    // 宿主对象中类型为 T 的值
+    pub fn as_host<T: Any>(&self) -> Option<&T> {
+        match self {
+            Value::Host(object) => object.data.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn get_class_name(&self) -> String {
+        match self {
+            Value::Null => "lambda.lang.Nothing".to_string(),
+            Value::Boolean(_) => "lambda.lang.Boolean".to_string(),
+            Value::Int(_) => "lambda.lang.Int".to_string(),
+            Value::BigInt(_) => "lambda.lang.BigInt".to_string(),
+            Value::Float(_) => "lambda.lang.Float".to_string(),
+            Value::BigDecimal(_) => "lambda.lang.BigDecimal".to_string(),
+            Value::Char(_) => "lambda.lang.Char".to_string(),
+            Value::String(_) => "lambda.lang.String".to_string(),
+            Value::Object(object) => object.borrow().class.name.clone(),
+            Value::Host(object) => object.class_name.clone(),
+        }
+    }
+
+    pub fn is_instance_of(&self, class_name: &str) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Object(object) => object.borrow().class.is_subclass_of(class_name),
+            Value::String(_) if class_name == "lambda.lang.CharSequence" => true,
+            _ => class_name == "lambda.lang.Any" || self.get_class_name() == class_name,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::BigInt(a), Value::BigInt(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::BigDecimal(a), Value::BigDecimal(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Object(a), Value::Object(b)) => Rc::ptr_eq(a, b),
+            (Value::Host(a), Value::Host(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Boolean(value) => write!(f, "{}", value),
+            Value::Int(value) => write!(f, "{}", value),
+            Value::BigInt(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{:?}", value),
+            Value::BigDecimal(value) => write!(f, "{}", value),
+            Value::Char(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", value),
+            Value::Object(object) => write!(f, "{}@{:p}", object.borrow().class.name, Rc::as_ptr(object)),
+            Value::Host(object) => write!(f, "{}@{:p}", object.class_name, Rc::as_ptr(object)),
+        }
+    }
+}
+
+impl Debug for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::String(value) => write!(f, "{:?}", value),
+            Value::Char(value) => write!(f, "{:?}", value),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
@@ -0,0 +1,573 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use lambda_bytecode::bytecode::bytecode::Bytecode;
+use lambda_bytecode::bytecode::archive::Archive;
+use lambda_bytecode::bytecode::module::Module;
+use lambda_bytecode::bytecode::verifier::{count_parameters, verify_module};
+use lambda_bytecode::compiler::INITIALIZER_NAME;
+use crate::class::Class;
+use crate::error::{RuntimeError, RuntimeResult, StackTraceElement};
+use crate::frame::Frame;
+use crate::function::{Function, Handler, Variable};
+use crate::heap::{Handle, Heap, HeapStats};
+use crate::loader::{ClassEntry, ClassLoader};
+use crate::native::{is_compatible, NativeRegistry};
+use crate::operator::{apply_binary, apply_default, apply_unary};
+use crate::pool::ConstantPool;
+use crate::value::{Object, Value};
+
+pub const MAX_CALL_DEPTH: usize = 1024;
+
+// 已经检查但尚未加入虚拟机的定义
+#[derive(Default)]
+struct PendingDefinitions {
+    classes: Vec<ClassEntry>,
+    functions: Vec<Function>,
+    class_names: HashSet<String>,
+    function_names: HashSet<String>,
+}
+
+pub struct VirtualMachine {
+    pub functions: HashMap<String, Rc<Function>>,
+    pub loader: ClassLoader,
+    pub natives: NativeRegistry,
+    pub singletons: HashMap<String, Value>,
+    pub globals: HashMap<String, Value>,
+    pub frames: Vec<Frame>,
+    pub heap: Heap,
+    pub handles: HashMap<Handle, Value>,
+    next_handle: usize,
+}
+
+impl Default for VirtualMachine {
+    fn default() -> Self { Self::new() }
+}
+
+impl VirtualMachine {
+    pub fn new() -> Self {
+        let mut globals = HashMap::new();
+        globals.insert("null".to_string(), Value::Null);
+        globals.insert("true".to_string(), Value::Boolean(true));
+        globals.insert("false".to_string(), Value::Boolean(false));
+        VirtualMachine {
+            functions: HashMap::new(),
+            loader: ClassLoader::new(),
+            natives: NativeRegistry::new(),
+            singletons: HashMap::new(),
+            globals,
+            frames: Vec::new(),
+            heap: Heap::new(),
+            handles: HashMap::new(),
+            next_handle: 0,
+        }
+    }
+
+    // 全部检查通过后才修改虚拟机的状态，加载失败的模块不会留下任何定义
+    pub fn load_module(&mut self, module: &Module) -> RuntimeResult<()> {
+        let mut pending = PendingDefinitions::default();
+        self.prepare_module(module, &mut pending)?;
+        self.define_all(pending)
+    }
+
+    // 检查模块并生成其中的类和函数，名字不能与虚拟机中或之前准备好的定义重复
+    fn prepare_module(&self, module: &Module, pending: &mut PendingDefinitions) -> RuntimeResult<()> {
+        verify_module(module).map_err(|error| RuntimeError::new(format!("Invalid module: {}", error).as_str()))?;
+        let constants = Rc::new(ConstantPool::new(module.constants.clone()));
+        let get_name = |index: usize| -> RuntimeResult<String> {
+            constants.get_string(index).map(|name| name.to_string())
+        };
+        // 先链接所有本地函数，有未注册的声明时整个模块都不加载
+        let mut natives = Vec::new();
+        let mut unresolved = Vec::new();
+        for index in &module.natives {
+            let (name, signature) = constants.get_function(*index)?;
+            match self.natives.get(name) {
+                Some(native) if is_compatible(signature, &native.signature) => {
+                    let parameters = count_parameters(signature).unwrap_or(0); // 签名已经过校验
+                    natives.push(Function::native(name.to_string(), parameters, native.function.clone()));
+                }
+                Some(native) => unresolved.push(format!("{} {} (registered as {})", name, signature, native.signature)),
+                None => unresolved.push(format!("{} {}", name, signature)),
+            }
+        }
+        if !unresolved.is_empty() {
+            return Err(RuntimeError::new(format!("Unresolved native functions: {}", unresolved.join(", ")).as_str()));
+        }
+        // 类在第一次使用时才链接，父类和接口可以在之后加载的模块中定义
+        let mut classes = Vec::new();
+        for definition in &module.classes {
+            let get_names = |indices: &[usize]| indices.iter().map(|index| get_name(*index)).collect::<RuntimeResult<Vec<_>>>();
+            let methods = definition.methods.iter().map(|index| get_name(module.functions[*index].name)).collect::<RuntimeResult<_>>()?;
+            let name = get_name(definition.name)?;
+            if self.loader.is_defined(&name) || !pending.class_names.insert(name.clone()) {
+                return Err(RuntimeError::new(format!("Class '{}' is already defined", name).as_str()));
+            }
+            classes.push(ClassEntry {
+                name,
+                super_class: definition.super_class.map(get_name).transpose()?,
+                interfaces: get_names(&definition.interfaces)?,
+                fields: get_names(&definition.fields)?,
+                methods,
+            });
+        }
+        let mut functions = Vec::new();
+        for definition in &module.functions {
+            let mut function = Function::new(
+                get_name(definition.name)?, definition.parameters, definition.locals, constants.clone(), definition.code.clone()
+            )?;
+            function.operator = definition.operator;
+            function.source_file = module.source_file.clone();
+            function.lines = definition.lines.clone();
+            for handler in &definition.handlers {
+                let get_index = |offset: usize| function.offsets.partition_point(|start| *start < offset);
+                function.handlers.push(Handler {
+                    start: get_index(handler.start),
+                    end: get_index(handler.end),
+                    handler: get_index(handler.handler),
+                    class: match handler.class {
+                        Some(class) => Some(constants.get_class_name(class)?.to_string()),
+                        None => None,
+                    },
+                });
+            }
+            for variable in &definition.variables {
+                let get_index = |offset: usize| function.offsets.partition_point(|start| *start < offset);
+                function.variables.push(Variable {
+                    name: get_name(variable.name)?,
+                    value_type: get_name(variable.value_type)?,
+                    slot: variable.slot,
+                    start: get_index(variable.start),
+                    end: get_index(variable.end),
+                });
+            }
+            functions.push(function);
+        }
+        // 替换已有的函数会让按名字调用和已生成的虚方法表执行不同的代码
+        for function in natives.iter().chain(&functions) {
+            if self.functions.contains_key(&function.name) || !pending.function_names.insert(function.name.clone()) {
+                return Err(RuntimeError::new(format!("Function '{}' is already defined", function.name).as_str()));
+            }
+        }
+        pending.classes.extend(classes);
+        pending.functions.extend(natives.into_iter().chain(functions));
+        Ok(())
+    }
+
+    fn define_all(&mut self, pending: PendingDefinitions) -> RuntimeResult<()> {
+        for class in pending.classes {
+            self.loader.define(class)?; // 已检查过不会重复
+        }
+        for function in pending.functions {
+            self.functions.insert(function.name.clone(), Rc::new(function));
+        }
+        Ok(())
+    }
+
+    // 类是延迟链接的，归档中的模块可以按任意顺序加载。
+    // 所有模块都检查通过后才一起加入，任何一个模块失败时整个归档都不加载
+    pub fn load_archive(&mut self, archive: &Archive) -> RuntimeResult<()> {
+        let mut pending = PendingDefinitions::default();
+        for (name, module) in &archive.entries {
+            self.prepare_module(module, &mut pending).map_err(|mut error| {
+                error.message = format!("{}: {}", name, error.message);
+                error
+            })?;
+        }
+        self.define_all(pending)
+    }
+
+    // 加载归档并以无参数调用清单中的入口函数
+    pub fn run(&mut self, archive: &Archive) -> RuntimeResult<Value> {
+        let entry_point = archive.manifest.entry_point.as_ref().ok_or_else(|| {
+            RuntimeError::new("Archive has no entry point")
+        })?;
+        self.load_archive(archive)?;
+        self.invoke(entry_point, vec![])
+    }
+
+    // 按名字取得类，未链接时先链接
+    pub fn get_class(&self, name: &str) -> RuntimeResult<Rc<Class>> {
+        self.loader.load_class(name, &self.functions)
+    }
+
+    // 解析常量池中的类引用，结果缓存在常量池中
+    fn resolve_class(&self, constants: &ConstantPool, index: usize) -> RuntimeResult<Rc<Class>> {
+        constants.resolve_class(index, |name| self.get_class(name))
+    }
+
+    // 回收不可达的对象，根是调用栈中的局部变量和操作数、全局变量、单例和句柄
+    pub fn gc(&mut self) -> usize {
+        let frames = self.frames.iter().flat_map(|frame| frame.locals.iter().chain(&frame.stack));
+        let roots = frames.chain(self.globals.values()).chain(self.singletons.values()).chain(self.handles.values());
+        self.heap.collect(roots)
+    }
+
+    pub fn get_heap_stats(&self) -> HeapStats { self.heap.get_stats() }
+
+    // 创建对象，堆中对象过多时先回收
+    pub fn allocate(&mut self, class: Rc<Class>) -> Value {
+        if self.heap.should_collect() {
+            self.gc();
+        }
+        self.heap.allocate(class)
+    }
+
+    // 让宿主代码保存的对象在回收时保持可达
+    pub fn pin(&mut self, value: Value) -> Handle {
+        let handle = Handle(self.next_handle);
+        self.next_handle += 1;
+        self.handles.insert(handle, value);
+        handle
+    }
+
+    pub fn get_pinned(&self, handle: Handle) -> Option<&Value> { self.handles.get(&handle) }
+
+    pub fn unpin(&mut self, handle: Handle) -> Option<Value> { self.handles.remove(&handle) }
+
+    pub fn get_function(&self, name: &str) -> RuntimeResult<Rc<Function>> {
+        self.functions.get(name).cloned().ok_or_else(|| {
+            RuntimeError::new(format!("Unresolved function '{}'", name).as_str())
+        })
+    }
+
+    pub fn invoke(&mut self, name: &str, arguments: Vec<Value>) -> RuntimeResult<Value> {
+        let function = self.get_function(name)?;
+        if arguments.len() != function.parameters {
+            return Err(RuntimeError::new(format!(
+                "Function '{}' expects {} arguments, but got {}", name, function.parameters, arguments.len()
+            ).as_str()));
+        }
+        if let Some(native) = &function.native {
+            return native.clone()(self, arguments);
+        }
+        let base = self.frames.len();
+        self.push_frame(function, arguments)?;
+        self.execute(base)
+    }
+
+    // 调用指令使用：本地函数直接执行并把结果压入调用者的操作数栈，其他函数压入新的栈帧
+    fn call(&mut self, function: Rc<Function>, arguments: Vec<Value>) -> RuntimeResult<()> {
+        match &function.native {
+            Some(native) => {
+                let value = native.clone()(self, arguments)?;
+                self.frames.last_mut().unwrap().push(value);
+                Ok(())
+            }
+            None => self.push_frame(function, arguments),
+        }
+    }
+
+    fn push_frame(&mut self, function: Rc<Function>, arguments: Vec<Value>) -> RuntimeResult<()> {
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::new("Stack overflow"));
+        }
+        self.frames.push(Frame::new(function, arguments));
+        Ok(())
+    }
+
+    fn execute(&mut self, base: usize) -> RuntimeResult<Value> {
+        loop {
+            match self.step() {
+                Ok(Some(value)) => {
+                    self.frames.pop();
+                    if self.frames.len() == base {
+                        return Ok(value);
+                    }
+                    self.frames.last_mut().unwrap().push(value);
+                }
+                Ok(None) => {}
+                Err(mut error) => {
+                    if let Some(exception) = &error.exception && self.unwind(base, exception) {
+                        continue;
+                    }
+                    // 本地函数重入时内层已经记录了它的帧，这里接上外层的帧
+                    error.stack_trace.extend(self.get_stack_trace(base));
+                    self.frames.truncate(base);
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    // 从栈顶向下查找能处理该异常的帧，找到时丢弃其上的帧并跳转到处理代码。
+    // 只有 Throw 产生的错误带有异常值，虚拟机内部错误不经过处理器直接返回
+    fn unwind(&mut self, base: usize, exception: &Value) -> bool {
+        for depth in (base..self.frames.len()).rev() {
+            let frame = &mut self.frames[depth];
+            let Some(handler) = frame.function.find_handler(frame.pc.saturating_sub(1), exception) else {
+                continue;
+            };
+            frame.pc = handler;
+            frame.stack.clear();
+            frame.push(exception.clone());
+            self.frames.truncate(depth + 1);
+            return true;
+        }
+        false
+    }
+
+    pub fn get_stack_trace(&self, base: usize) -> Vec<StackTraceElement> {
+        self.frames[base..].iter().rev().map(|frame| {
+            let offset = frame.function.get_offset(frame.pc.saturating_sub(1));
+            StackTraceElement {
+                function: frame.function.name.clone(),
+                offset,
+                source_file: frame.function.source_file.clone(),
+                position: frame.function.get_position(offset),
+                variables: frame.get_variables(),
+            }
+        }).collect()
+    }
+
+    // 执行一条指令，函数返回时得到返回值
+    fn step(&mut self) -> RuntimeResult<Option<Value>> {
+        let frame = self.frames.last_mut().unwrap();
+        let function = frame.function.clone();
+        let Some(instruction) = function.instructions.get(frame.pc) else {
+            return Ok(Some(Value::Null)); // 函数末尾隐式返回
+        };
+        frame.pc += 1;
+        match instruction {
+            Bytecode::Metadata { .. } | Bytecode::Nop | Bytecode::Constant(_) => {}
+            Bytecode::LoadConst(index) => {
+                let value = function.constants.get_value(*index)?;
+                frame.push(value);
+            }
+            Bytecode::GetObject(index) => {
+                let name = function.constants.get_class_name(*index)?;
+                let value = self.get_singleton(name)?;
+                self.frames.last_mut().unwrap().push(value);
+            }
+            Bytecode::NewObject(index) => {
+                let class = self.resolve_class(&function.constants, *index)?;
+                let object = self.allocate(class);
+                self.frames.last_mut().unwrap().push(object);
+            }
+            Bytecode::Load => {
+                let name = frame.pop()?;
+                let name = name.as_str().ok_or_else(|| RuntimeError::new("Expected a global name on the stack"))?;
+                let value = self.globals.get(name).cloned().ok_or_else(|| {
+                    RuntimeError::new(format!("Unresolved global '{}'", name).as_str())
+                })?;
+                self.frames.last_mut().unwrap().push(value);
+            }
+            Bytecode::Store(index) => {
+                let value = frame.pop()?;
+                frame.set_local(*index, value)?;
+            }
+            Bytecode::LoadLocal(index) => {
+                let value = frame.get_local(*index)?;
+                frame.push(value);
+            }
+            Bytecode::Pop => {
+                frame.pop()?;
+            }
+            Bytecode::Dup => {
+                let value = frame.peek()?.clone();
+                frame.push(value);
+            }
+            Bytecode::Swap => {
+                let a = frame.pop()?;
+                let b = frame.pop()?;
+                frame.push(a);
+                frame.push(b);
+            }
+            Bytecode::Invoke(index) => {
+                let (name, _) = function.constants.get_function(*index)?;
+                let callee = self.get_function(name)?;
+                let frame = self.frames.last_mut().unwrap();
+                if frame.stack.len() < callee.parameters {
+                    return Err(RuntimeError::new("Operand stack underflow"));
+                }
+                let arguments = frame.stack.split_off(frame.stack.len() - callee.parameters);
+                self.call(callee, arguments)?;
+            }
+            Bytecode::InvokeVirtual(index, arguments)
+            | Bytecode::InvokeSpecial(index, arguments)
+            | Bytecode::InvokeInterface(index, arguments) => {
+                let (name, _) = function.constants.get_function(*index)?;
+                let callee = self.resolve_method(instruction, name, *arguments)?;
+                let frame = self.frames.last_mut().unwrap();
+                let arguments = frame.stack.split_off(frame.stack.len() - arguments - 1);
+                self.call(callee, arguments)?;
+            }
+            Bytecode::Return => {
+                return Ok(Some(frame.stack.pop().unwrap_or(Value::Null)));
+            }
+            Bytecode::Jump(offset) => {
+                frame.pc = Self::get_jump_target(&function, *offset)?;
+            }
+            Bytecode::JumpIfTrue(offset) => {
+                if Self::pop_condition(frame)? {
+                    frame.pc = Self::get_jump_target(&function, *offset)?;
+                }
+            }
+            Bytecode::JumpIfFalse(offset) => {
+                if !Self::pop_condition(frame)? {
+                    frame.pc = Self::get_jump_target(&function, *offset)?;
+                }
+            }
+            Bytecode::GetField(index) => {
+                let name = function.constants.get_string(*index)?;
+                let object = frame.pop()?;
+                let target = Self::get_object(&object)?.borrow();
+                let slot = Self::get_field_slot(&function, frame.pc - 1, &target.class, name)?;
+                let value = target.fields[slot].clone();
+                drop(target);
+                frame.push(value);
+            }
+            Bytecode::SetField(index) => {
+                let name = function.constants.get_string(*index)?;
+                let value = frame.pop()?;
+                let object = frame.pop()?;
+                let mut target = Self::get_object(&object)?.borrow_mut();
+                let slot = Self::get_field_slot(&function, frame.pc - 1, &target.class, name)?;
+                target.fields[slot] = value;
+            }
+            Bytecode::CheckCast(index) => {
+                let name = function.constants.get_class_name(*index)?;
+                let value = frame.peek()?;
+                if !value.is_null() && !value.is_instance_of(name) {
+                    return Err(RuntimeError::new(
+                        format!("Cannot cast {} to {}", value.get_class_name(), name).as_str()
+                    ));
+                }
+            }
+            Bytecode::InstanceOf(index) => {
+                let name = function.constants.get_class_name(*index)?;
+                let value = frame.pop()?;
+                frame.push(Value::Boolean(value.is_instance_of(name)));
+            }
+            Bytecode::Throw => {
+                let value = frame.pop()?;
+                return Err(RuntimeError::exception(value));
+            }
+            operator @ (Bytecode::Neg | Bytecode::Pos | Bytecode::Not) => {
+                let value = frame.pop()?;
+                match apply_unary(operator, &value) {
+                    Some(result) => frame.push(result?),
+                    None => self.invoke_operator(operator, vec![value])?,
+                }
+            }
+            operator @ (Bytecode::Add
+            | Bytecode::Sub
+            | Bytecode::Mul
+            | Bytecode::Div
+            | Bytecode::Mod
+            | Bytecode::Pow
+            | Bytecode::And
+            | Bytecode::Or
+            | Bytecode::Equal
+            | Bytecode::NotEqual
+            | Bytecode::IdentityEqual
+            | Bytecode::IdentityNotEqual
+            | Bytecode::Less
+            | Bytecode::LessEqual
+            | Bytecode::Greater
+            | Bytecode::GreaterEqual) => {
+                let right = frame.pop()?;
+                let left = frame.pop()?;
+                match apply_binary(operator, &left, &right) {
+                    Some(result) => frame.push(result?),
+                    None => self.invoke_operator(operator, vec![left, right])?,
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // 根据调用指令确定实际执行的方法，name 为声明该方法的类名加方法名，接收者在参数之下
+    fn resolve_method(&self, instruction: &Bytecode, name: &str, arguments: usize) -> RuntimeResult<Rc<Function>> {
+        let Some((owner, method)) = name.rsplit_once('.') else {
+            return Err(RuntimeError::new(format!("'{}' is not a method name", name).as_str()));
+        };
+        let frame = self.frames.last().unwrap();
+        if frame.stack.len() < arguments + 1 {
+            return Err(RuntimeError::new("Operand stack underflow"));
+        }
+        let receiver = &frame.stack[frame.stack.len() - arguments - 1];
+        let class = Self::get_object(receiver)?.borrow().class.clone();
+        let class = match instruction {
+            Bytecode::InvokeSpecial(..) => self.get_class(owner)?, // 不经过动态分派
+            Bytecode::InvokeInterface(..) if !class.is_subclass_of(owner) => {
+                return Err(RuntimeError::new(
+                    format!("{} does not implement interface {}", class.name, owner).as_str()
+                ));
+            }
+            _ => class,
+        };
+        let callee = class.find_method(method).ok_or_else(|| {
+            RuntimeError::new(format!("No such method '{}' in {}", method, class.name).as_str())
+        })?;
+        if callee.parameters != arguments + 1 {
+            return Err(RuntimeError::new(format!(
+                "Method '{}' expects {} arguments, but got {}", callee.name, callee.parameters.saturating_sub(1), arguments
+            ).as_str()));
+        }
+        Ok(callee)
+    }
+
+    // 第一个操作数是对象时调用其类中的运算符函数，找不到时使用默认行为
+    fn invoke_operator(&mut self, operator: &Bytecode, arguments: Vec<Value>) -> RuntimeResult<()> {
+        let name = operator.get_operator_function_name().unwrap();
+        let class = Self::get_object(&arguments[0])?.borrow().class.clone();
+        match class.find_method(name) {
+            Some(method) if method.operator && method.parameters == arguments.len() => self.push_frame(method, arguments),
+            _ => {
+                let value = match arguments.as_slice() {
+                    [left, right] => apply_default(operator, left, right)?,
+                    _ => return Err(RuntimeError::new(format!(
+                        "Unsupported operand type for '{}': {}", name, class.name
+                    ).as_str())),
+                };
+                self.frames.last_mut().unwrap().push(value);
+                Ok(())
+            }
+        }
+    }
+
+    fn get_singleton(&mut self, name: &str) -> RuntimeResult<Value> {
+        if let Some(value) = self.singletons.get(name) {
+            return Ok(value.clone());
+        }
+        let class = self.get_class(name)?;
+        let value = self.allocate(class);
+        // 先登记再初始化，初始化代码中可以引用自身；初始化失败时移除，下次访问重新创建
+        self.singletons.insert(name.to_string(), value.clone());
+        let initializer = format!("{}.{}", name, INITIALIZER_NAME);
+        if self.functions.contains_key(&initializer)
+            && let Err(error) = self.invoke(&initializer, vec![value.clone()]) {
+            self.singletons.remove(name);
+            return Err(error);
+        }
+        Ok(value)
+    }
+
+    fn get_field_slot(function: &Function, index: usize, class: &Rc<Class>, name: &str) -> RuntimeResult<usize> {
+        function.get_field_slot(index, class, name).ok_or_else(|| {
+            RuntimeError::new(format!("No such field '{}' in {}", name, class.name).as_str())
+        })
+    }
+
+    fn get_object(value: &Value) -> RuntimeResult<&Rc<RefCell<Object>>> {
+        match value {
+            Value::Object(object) => Ok(object),
+            Value::Null => Err(RuntimeError::new("Null pointer dereference")),
+            _ => Err(RuntimeError::new(format!("Expected an object, but got {}", value.get_class_name()).as_str())),
+        }
+    }
+
+    fn get_jump_target(function: &Function, offset: usize) -> RuntimeResult<usize> {
+        function.get_index(offset).ok_or_else(|| {
+            RuntimeError::new(format!("Invalid jump target {} in function '{}'", offset, function.name).as_str())
+        })
+    }
+
+    fn pop_condition(frame: &mut Frame) -> RuntimeResult<bool> {
+        let value = frame.pop()?;
+        value.as_bool().ok_or_else(|| {
+            RuntimeError::new(format!("Expected a boolean condition, but got {}", value.get_class_name()).as_str())
+        })
+    }
+}